@@ -0,0 +1,193 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Data, DeriveInput, Error, Fields, Ident, Result, Token,
+};
+
+/// A view declared as `Name(mut field, field, ..)`.
+struct View {
+    name: Ident,
+    fields: Punctuated<Field, Token![,]>,
+}
+
+/// A field of a view, borrowed exclusively if it is prefixed with `mut`.
+struct Field {
+    mutable: bool,
+    name: Ident,
+}
+
+impl Parse for View {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name = input.parse()?;
+        let content;
+        parenthesized!(content in input);
+        let fields = content.parse_terminated(Field::parse, Token![,])?;
+        Ok(Self { name, fields })
+    }
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mutable = input.parse::<Option<Token![mut]>>()?.is_some();
+        let name = input.parse()?;
+        Ok(Self { mutable, name })
+    }
+}
+
+/// Generates views of the fields of a struct, to be used with `Armoire::fork_by` and `Pairs::fork_by`. Views are
+/// declared with `#[fork(Name(mut field, field, ..), ..)]`, where fields prefixed with `mut` are borrowed exclusively.
+/// Each view is a struct of references to its fields that implements `Item` and `View`; it reads as a tuple of shared
+/// references to its fields, in the order they are declared in the view.
+#[proc_macro_derive(Fork, attributes(fork))]
+pub fn fork(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "`Fork` can not be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`Fork` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`Fork` can only be derived for structs",
+            ))
+        }
+    };
+    if fields.len() > 64 {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`Fork` supports at most 64 fields",
+        ));
+    }
+
+    let mut views = Vec::new();
+    for attribute in input
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("fork"))
+    {
+        views.extend(attribute.parse_args_with(Punctuated::<View, Token![,]>::parse_terminated)?);
+    }
+
+    let source = &input.ident;
+    let visibility = &input.vis;
+    let lifetime = syn::Lifetime::new("'a", Span::call_site());
+    let mut output = proc_macro2::TokenStream::new();
+    for view in views {
+        let name = &view.name;
+        let mut reads = 0u64;
+        let mut writes = 0u64;
+        let mut members = Vec::new();
+        for field in &view.fields {
+            let Some(index) = fields
+                .iter()
+                .position(|candidate| candidate.ident.as_ref() == Some(&field.name))
+            else {
+                return Err(Error::new_spanned(
+                    &field.name,
+                    format!("`{source}` has no field `{}`", field.name),
+                ));
+            };
+            let bit = 1u64 << index;
+            if (reads | writes) & bit != 0 {
+                return Err(Error::new_spanned(
+                    &field.name,
+                    format!("field `{}` is already part of the view", field.name),
+                ));
+            }
+            if field.mutable {
+                writes |= bit;
+            } else {
+                reads |= bit;
+            }
+            members.push((field, &fields[index].ty));
+        }
+
+        let declarations = members.iter().map(|(field, ty)| {
+            let name = &field.name;
+            if field.mutable {
+                quote!(pub #name: &#lifetime mut #ty)
+            } else {
+                quote!(pub #name: &#lifetime #ty)
+            }
+        });
+        let names = members
+            .iter()
+            .map(|(field, _)| &field.name)
+            .collect::<Vec<_>>();
+        let types = members.iter().map(|(_, ty)| ty).collect::<Vec<_>>();
+        let borrows = members.iter().map(|(field, _)| {
+            let name = &field.name;
+            if field.mutable {
+                quote!(#name: &mut (*value).#name)
+            } else {
+                quote!(#name: &(*value).#name)
+            }
+        });
+        output.extend(quote! {
+            #visibility struct #name<#lifetime> {
+                #(#declarations,)*
+            }
+
+            impl<#lifetime> ::armoire::Item for #name<#lifetime> {
+                type Read = (#(&#lifetime #types,)*);
+                type Write<'b> = #name<'b> where Self: 'b;
+
+                const EXCLUSIVE: bool = #writes != 0;
+
+                #[inline]
+                fn read(self) -> Self::Read {
+                    #(let #names: &#lifetime #types = self.#names;)*
+                    (#(#names,)*)
+                }
+
+                #[inline]
+                fn write<'b>(self) -> Self::Write<'b>
+                where
+                    Self: 'b,
+                {
+                    self
+                }
+
+                fn borrows(&self, borrows: &mut ::std::vec::Vec<(::std::ops::Range<usize>, bool)>) {
+                    #(::armoire::Item::borrows(&self.#names, borrows);)*
+                }
+            }
+
+            unsafe impl<#lifetime> ::armoire::View<#lifetime, #source> for #name<#lifetime> {
+                const READS: u64 = #reads;
+                const WRITES: u64 = #writes;
+
+                #[inline]
+                unsafe fn view(value: *mut #source) -> Self {
+                    Self {
+                        #(#borrows,)*
+                    }
+                }
+            }
+        });
+    }
+    Ok(output)
+}
@@ -0,0 +1,44 @@
+use armoire::*;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rayon::prelude::*;
+
+const COUNTS: [usize; 2] = [1_000, 100_000];
+
+fn inserts(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("defer/insert");
+    for count in COUNTS {
+        group.bench_function(BenchmarkId::from_parameter(count), |bencher| {
+            bencher.iter_batched_ref(
+                || (0..count).collect::<Armoire<_>>(),
+                |armoire| {
+                    let (pairs, defer) = armoire.defer();
+                    pairs.par_iter().for_each(|(_, &value)| {
+                        defer.insert(value);
+                    });
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+fn removes(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("defer/remove");
+    for count in COUNTS {
+        group.bench_function(BenchmarkId::from_parameter(count), |bencher| {
+            bencher.iter_batched_ref(
+                || (0..count).collect::<Armoire<_>>(),
+                |armoire| {
+                    let (pairs, defer) = armoire.defer();
+                    pairs.par_iter().for_each(|(key, _)| {
+                        defer.remove([key]);
+                    });
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, inserts, removes);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+use armoire::*;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rayon::prelude::*;
+
+const COUNTS: [usize; 2] = [1_000, 100_000];
+
+fn inserts(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("resolve/insert");
+    for count in COUNTS {
+        group.bench_function(BenchmarkId::from_parameter(count), |bencher| {
+            bencher.iter_batched_ref(
+                || {
+                    let mut armoire = (0..count).collect::<Armoire<_>>();
+                    let (pairs, defer) = armoire.defer();
+                    pairs.par_iter().for_each(|(_, &value)| {
+                        defer.insert(value);
+                    });
+                    armoire
+                },
+                |armoire| armoire.resolve(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+fn removes(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("resolve/remove");
+    for count in COUNTS {
+        group.bench_function(BenchmarkId::from_parameter(count), |bencher| {
+            bencher.iter_batched_ref(
+                || {
+                    let mut armoire = (0..count * 2).collect::<Armoire<_>>();
+                    let (pairs, defer) = armoire.defer();
+                    pairs.par_iter().for_each(|(key, &value)| {
+                        if value % 2 == 0 {
+                            defer.remove([key]);
+                        }
+                    });
+                    armoire
+                },
+                |armoire| armoire.resolve(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, inserts, removes);
+criterion_main!(benches);
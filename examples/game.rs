@@ -5,6 +5,9 @@ use std::{
     time::{Duration, Instant},
 };
 
+/// The distance within which a player keeps chasing its target.
+const REACH: u64 = 10_000_000;
+
 #[derive(Clone, Default)]
 pub struct Player {
     pub position: [f64; 2],
@@ -19,6 +22,7 @@ pub struct Enemy {
 }
 
 #[derive(Clone)]
+#[repr(u8)]
 pub enum Entity {
     Player(Player),
     Enemy(Enemy),
@@ -143,23 +147,40 @@ fn main() {
             });
 
         entities.scope(|mut entities, defer| {
-            let (mut players, targets) = entities.fork(|key, entity| match entity {
-                Entity::Player(player) => (
-                    Some((key, &mut player.target, &player.position)),
-                    (key, &player.position),
-                ),
-                Entity::Enemy(enemy) => (None, (key, &enemy.position)),
-            });
-            players.par_iter_mut().for_each(|player| {
-                defer.insert(Entity::Player(Player::default()));
-                if let Some((key, target, position)) = player {
-                    let near = targets
-                        .par_iter()
-                        .filter(|pair| pair.0 != key)
-                        .min_by_key(|pair| distance(*position, *pair.1));
-                    if let Some(near) = near {
-                        *target = Some(near.0);
+            // Each projection only borrows the fields it needs, such that players can retarget while targets are read.
+            let (mut players, targets) = entities.fork_filter(
+                |key, entity| unsafe {
+                    match *entity {
+                        Entity::Player(Player {
+                            ref mut target,
+                            ref position,
+                            ..
+                        }) => Some((key, target, position)),
+                        Entity::Enemy(_) => None,
                     }
+                },
+                |key, entity| unsafe {
+                    match *entity {
+                        Entity::Player(Player { ref position, .. })
+                        | Entity::Enemy(Enemy { ref position, .. }) => Some((key, position)),
+                    }
+                },
+            );
+            players.par_iter_mut().for_each(|(key, target, position)| {
+                defer.insert(Entity::Player(Player::default()));
+                // Keep chasing the current target as long as it exists and is in reach.
+                let chasing = target
+                    .and_then(|target| targets.get(target))
+                    .is_some_and(|(_, near)| distance(*position, *near) < REACH);
+                if chasing {
+                    return;
+                }
+                let near = targets
+                    .par_iter()
+                    .filter(|pair| pair.0 != key)
+                    .min_by_key(|pair| distance(*position, *pair.1));
+                if let Some(near) = near {
+                    *target = Some(near.0);
                 }
             });
         });
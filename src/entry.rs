@@ -0,0 +1,234 @@
+use crate::{log::Log, take, tick::Clock, Defer, Event, Hook, Key, KeyType, Pair, Slot, Tag};
+use std::mem::replace;
+
+/// A view into a single key of an [`crate::Armoire`] or [`crate::Pairs`].
+pub enum Entry<'a, T, K = Key> {
+    /// The key refers to an inserted value.
+    Occupied(OccupiedEntry<'a, T, K>),
+    /// The key has been reserved but no value has been inserted for it yet.
+    Vacant(VacantEntry<'a, T, K>),
+    /// The key is outdated, has never been reserved or belongs to another armoire.
+    Stale(StaleEntry<K>),
+}
+
+pub struct OccupiedEntry<'a, T, K = Key> {
+    index: usize,
+    slots: &'a mut Vec<Slot>,
+    pairs: &'a mut Vec<Pair<T, K>>,
+    pending: &'a mut Vec<Key>,
+    clock: Clock<'a>,
+    observers: Observers<'a, T, K>,
+}
+
+pub struct VacantEntry<'a, T, K = Key> {
+    key: K,
+    slots: &'a mut Vec<Slot>,
+    pairs: &'a mut Vec<Pair<T, K>>,
+    clock: Clock<'a>,
+    observers: Observers<'a, T, K>,
+}
+
+pub struct StaleEntry<K = Key> {
+    key: K,
+}
+
+/// The hooks and the log of the armoire of an entry, along with the defer handed to the hooks.
+pub(crate) struct Observers<'a, T, K> {
+    pub on_insert: &'a mut [Hook<T, K>],
+    pub on_remove: &'a mut [Hook<T, K>],
+    pub log: Option<&'a mut Log<T, K>>,
+    pub defer: Defer<'a, T, K>,
+}
+
+impl<T, K> Observers<'_, T, K> {
+    #[inline]
+    pub fn reborrow(&mut self) -> Observers<'_, T, K> {
+        Observers {
+            on_insert: self.on_insert,
+            on_remove: self.on_remove,
+            log: self.log.as_deref_mut(),
+            defer: self.defer.clone(),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn entry<'a, T, K: KeyType>(
+    key: K,
+    tag: Tag,
+    last: u32,
+    slots: &'a mut Vec<Slot>,
+    pairs: &'a mut Vec<Pair<T, K>>,
+    pending: &'a mut Vec<Key>,
+    clock: Clock<'a>,
+    observers: Observers<'a, T, K>,
+) -> Entry<'a, T, K> {
+    let raw: Key = key.into();
+    if !tag.accepts(raw) || raw.index >= last {
+        return Entry::Stale(StaleEntry { key });
+    }
+
+    let at = raw.index as usize;
+    if at >= slots.len() {
+        slots.resize(last as _, Slot::ZERO);
+    }
+
+    let slot = slots[at];
+    if slot.generation != raw.generation {
+        Entry::Stale(StaleEntry { key })
+    } else if slot.index == u32::MAX {
+        Entry::Vacant(VacantEntry {
+            key,
+            slots,
+            pairs,
+            clock,
+            observers,
+        })
+    } else {
+        Entry::Occupied(OccupiedEntry {
+            index: slot.index as _,
+            slots,
+            pairs,
+            pending,
+            clock,
+            observers,
+        })
+    }
+}
+
+impl<'a, T, K: KeyType> Entry<'a, T, K> {
+    #[inline]
+    pub fn key(&self) -> K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Stale(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `value` if the entry is vacant. Returns `None` if the entry is stale, in which case `value` is dropped.
+    #[inline]
+    pub fn or_insert(self, value: T) -> Option<&'a mut T> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Inserts the value produced by `with` if the entry is vacant. Returns `None` if the entry is stale, in which
+    /// case `with` is not called.
+    #[inline]
+    pub fn or_insert_with<F: FnOnce() -> T>(self, with: F) -> Option<&'a mut T> {
+        match self {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => Some(entry.insert(with())),
+            Entry::Stale(_) => None,
+        }
+    }
+
+    #[inline]
+    pub fn or_default(self) -> Option<&'a mut T>
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    #[inline]
+    pub fn and_modify<F: FnOnce(&mut T)>(mut self, modify: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            modify(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, T, K: KeyType> OccupiedEntry<'a, T, K> {
+    #[inline]
+    pub fn key(&self) -> K {
+        self.pairs[self.index].0
+    }
+
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.pairs[self.index].1
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.clock.change(self.key().into());
+        &mut self.pairs[self.index].1
+    }
+
+    #[inline]
+    pub fn into_mut(self) -> &'a mut T {
+        self.clock.change(self.key().into());
+        &mut self.pairs[self.index].1
+    }
+
+    /// Replaces the value of the entry and returns the previous one. The key remains valid.
+    #[inline]
+    pub fn insert(&mut self, value: T) -> T {
+        replace(self.get_mut(), value)
+    }
+
+    /// Removes the value of the entry, invalidating its key. The remove hooks of the armoire are called with the
+    /// removed value.
+    #[inline]
+    pub fn remove(self) -> T {
+        let key = self.key();
+        let mut value = match take(key.into(), self.pairs, self.slots, self.pending) {
+            Some(value) => value,
+            None => unreachable!("an occupied entry must have a value"),
+        };
+        let Observers {
+            on_remove,
+            log,
+            defer,
+            ..
+        } = self.observers;
+        if let Some(log) = log {
+            log.removed(key, &value);
+        }
+        for hook in on_remove.iter_mut() {
+            hook(key, &mut value, &defer);
+        }
+        value
+    }
+}
+
+impl<'a, T, K: KeyType> VacantEntry<'a, T, K> {
+    #[inline]
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    /// Inserts `value` at the key of the entry. The insert hooks of the armoire are called with the inserted value.
+    #[inline]
+    pub fn insert(self, value: T) -> &'a mut T {
+        let key: Key = self.key.into();
+        let index = self.pairs.len();
+        let initialized = self.slots[key.index as usize].initialize(key.generation, index as _);
+        debug_assert!(initialized);
+        self.pairs.push((self.key, value));
+        self.clock.add(key);
+        let Observers {
+            on_insert,
+            log,
+            defer,
+            ..
+        } = self.observers;
+        if let Some(log) = log {
+            log.push(Event::Inserted(self.key));
+        }
+        let value = &mut self.pairs[index].1;
+        for hook in on_insert.iter_mut() {
+            hook(self.key, value, &defer);
+        }
+        value
+    }
+}
+
+impl<K: KeyType> StaleEntry<K> {
+    #[inline]
+    pub fn key(&self) -> K {
+        self.key
+    }
+}
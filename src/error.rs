@@ -0,0 +1,60 @@
+use crate::Key;
+use std::{error, fmt};
+
+/// The reason why an operation on a key failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// The key is [`crate::Key::NULL`].
+    Null,
+    /// The key was reserved by another armoire.
+    Foreign,
+    /// The key has never been reserved.
+    Invalid,
+    /// The key is outdated; its value has been removed.
+    Stale,
+    /// The key is reserved but no value has been inserted for it.
+    Vacant,
+    /// The key was provided more than once.
+    Duplicate,
+    /// The key already has a value.
+    Occupied,
+    /// All `u32::MAX` key indices have been handed out.
+    Exhausted,
+}
+
+/// A failed insertion, carrying back the value that could not be inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertError<T, K = Key> {
+    pub key: K,
+    pub value: T,
+    pub error: Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Null => write!(f, "key is null"),
+            Error::Foreign => write!(f, "key was reserved by another armoire"),
+            Error::Invalid => write!(f, "key has never been reserved"),
+            Error::Stale => write!(f, "key is outdated"),
+            Error::Vacant => write!(f, "key has no value"),
+            Error::Duplicate => write!(f, "key was provided more than once"),
+            Error::Occupied => write!(f, "key already has a value"),
+            Error::Exhausted => write!(f, "key indices are exhausted"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl<T, K: fmt::Debug> fmt::Display for InsertError<T, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to insert key '{:?}': {}", self.key, self.error)
+    }
+}
+
+impl<T: fmt::Debug, K: fmt::Debug> error::Error for InsertError<T, K> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
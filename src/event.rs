@@ -0,0 +1,16 @@
+use crate::{InsertError, Key};
+
+/// Something that happened to a deferred operation while resolving an [`crate::Armoire`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<T, K = Key> {
+    /// The deferred value of the key has been inserted.
+    Inserted(K),
+    /// The value of the key has been replaced; the previous value is carried.
+    Replaced(K, T),
+    /// The value of the key has been removed.
+    Removed(K, T),
+    /// The reserved key has been released without ever receiving a value.
+    Released(K),
+    /// The deferred value could not be inserted.
+    Rejected(InsertError<T, K>),
+}
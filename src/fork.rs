@@ -1,151 +1,450 @@
-use crate::{utility::FullIterator, Key};
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
-use std::{marker::PhantomData, slice::from_raw_parts_mut};
+use crate::{join::Lookup, utility::FullIterator, Key, KeyType};
+use rayon::prelude::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use std::{marker::PhantomData, mem::size_of_val, ops::Range};
 
-pub struct Fork<'a, S, F>(*mut S, usize, F, PhantomData<&'a mut [S]>);
+/// One of the views of the elements of a slice split with `fork`. Elements are only ever accessed through their
+/// pointer, such that the items of one fork are never invalidated by a reference to a whole element from another.
+pub struct Fork<'a, S, F>(
+    *mut S,
+    usize,
+    F,
+    Lookup<'a>,
+    /// The ascending positions of the elements yielded by a filtered fork.
+    Option<Box<[u32]>>,
+    PhantomData<&'a mut [S]>,
+);
 
 pub trait Item {
     type Read;
-    type Write;
+    /// The item as yielded by the exclusive methods of a [`Fork`], which must not outlive the borrow of the fork.
+    type Write<'b>
+    where
+        Self: 'b;
     fn read(self) -> Self::Read;
-    fn write(self) -> Self::Write;
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b;
+
+    /// Whether the item borrows anything exclusively. A [`Fork`] of such items can only be accessed through its
+    /// exclusive methods, since its shared methods could hand out the same item more than once.
+    const EXCLUSIVE: bool = false;
+
+    /// Reports the address ranges borrowed by the item and whether they are borrowed exclusively. Debug builds use them
+    /// to check that the items of the forks of a value do not alias. Items that report nothing are not checked, which
+    /// is the default for items implemented outside of this crate unless they override this method.
+    fn borrows(&self, _borrows: &mut Vec<(Range<usize>, bool)>) {}
+}
+
+/// Projects an element of a forked slice into the item of a [`Fork`] through a pointer to it. Implemented for closures
+/// and for the projections of a [`crate::Schedule`], whose type can be named in [`crate::Access`].
+pub trait Project<S> {
+    type Item;
+
+    /// # Safety
+    /// `element` must be valid and the fields that the item borrows must not be borrowed elsewhere in a way that
+    /// conflicts with how the item borrows them.
+    unsafe fn project(&self, element: *mut S) -> Self::Item;
+}
+
+impl<S, T, F: Fn(*mut S) -> T> Project<S> for F {
+    type Item = T;
+
+    #[inline]
+    unsafe fn project(&self, element: *mut S) -> T {
+        self(element)
+    }
+}
+
+/// Panics with the key of the first element for which an exclusive borrow of one view overlaps a borrow of another.
+/// Does nothing in release builds.
+fn validate<T>(
+    data: *mut T,
+    count: usize,
+    key: fn(&T) -> Key,
+    views: impl Fn(*mut T) -> Vec<Vec<(Range<usize>, bool)>>,
+) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    for index in 0..count {
+        let views = views(unsafe { data.add(index) });
+        let overlaps = views.iter().enumerate().any(|(index, left)| {
+            views[index + 1..].iter().any(|right| {
+                left.iter().any(|(left, left_exclusive)| {
+                    right.iter().any(|(right, right_exclusive)| {
+                        (*left_exclusive || *right_exclusive)
+                            && left.start < right.end
+                            && right.start < left.end
+                    })
+                })
+            })
+        });
+        if overlaps {
+            let key = key(unsafe { &*data.add(index) });
+            panic!("the views of a fork overlap at key '{key:?}'");
+        }
+    }
 }
 
 impl<'a, T> Item for &'a T {
     type Read = &'a T;
-    type Write = &'a T;
+    type Write<'b>
+        = &'a T
+    where
+        Self: 'b;
 
     fn read(self) -> Self::Read {
         self
     }
 
-    fn write(self) -> Self::Write {
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b,
+    {
         self
     }
+
+    fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+        let start = *self as *const T as usize;
+        borrows.push((start..start + size_of_val(*self), false));
+    }
 }
 
 impl<'a, T> Item for &'a mut T {
     type Read = &'a T;
-    type Write = &'a mut T;
+    type Write<'b>
+        = &'b mut T
+    where
+        Self: 'b;
+
+    const EXCLUSIVE: bool = true;
 
     fn read(self) -> Self::Read {
         self
     }
 
-    fn write(self) -> Self::Write {
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b,
+    {
         self
     }
-}
 
-impl<I1: Item, I2: Item> Item for (I1, I2) {
-    type Read = (I1::Read, I2::Read);
-    type Write = (I1::Write, I2::Write);
-
-    fn read(self) -> Self::Read {
-        (self.0.read(), self.1.read())
+    fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+        let start = &**self as *const T as usize;
+        borrows.push((start..start + size_of_val(&**self), true));
     }
+}
 
-    fn write(self) -> Self::Write {
-        (self.0.write(), self.1.write())
-    }
+/// A view of some of the fields of a `T`, generated with `#[derive(Fork)]` to be used with
+/// [`crate::Armoire::fork_by`].
+///
+/// # Safety
+/// [`View::view`] must only borrow the fields whose bits are set in [`View::READS`] (shared) and [`View::WRITES`]
+/// (exclusive), where bit `i` stands for the `i`-th field of `T`.
+pub unsafe trait View<'a, T>: Item {
+    const READS: u64;
+    const WRITES: u64;
+
+    /// # Safety
+    /// `value` must be valid for `'a` and the fields of the view must not be borrowed elsewhere in a way that conflicts
+    /// with how the view borrows them.
+    unsafe fn view(value: *mut T) -> Self;
 }
 
-impl<I1: Item, I2: Item, I3: Item> Item for (I1, I2, I3) {
-    type Read = (I1::Read, I2::Read, I3::Read);
-    type Write = (I1::Write, I2::Write, I3::Write);
+macro_rules! tuple {
+    ($($item:ident $index:tt),+) => {
+        impl<$($item: Item),+> Item for ($($item,)+) {
+            type Read = ($($item::Read,)+);
+            type Write<'b> = ($($item::Write<'b>,)+) where Self: 'b;
 
-    fn read(self) -> Self::Read {
-        (self.0.read(), self.1.read(), self.2.read())
-    }
+            const EXCLUSIVE: bool = $($item::EXCLUSIVE)||+;
 
-    fn write(self) -> Self::Write {
-        (self.0.write(), self.1.write(), self.2.write())
-    }
+            fn read(self) -> Self::Read {
+                ($(self.$index.read(),)+)
+            }
+
+            fn write<'b>(self) -> Self::Write<'b>
+            where
+                Self: 'b,
+            {
+                ($(self.$index.write(),)+)
+            }
+
+            fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+                $(self.$index.borrows(borrows);)+
+            }
+        }
+    };
 }
 
+tuple!(I1 0);
+tuple!(I1 0, I2 1);
+tuple!(I1 0, I2 1, I3 2);
+tuple!(I1 0, I2 1, I3 2, I4 3);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6, I8 7);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6, I8 7, I9 8);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6, I8 7, I9 8, I10 9);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6, I8 7, I9 8, I10 9, I11 10);
+tuple!(I1 0, I2 1, I3 2, I4 3, I5 4, I6 5, I7 6, I8 7, I9 8, I10 9, I11 10, I12 11);
+
 impl<I: Item, const N: usize> Item for [I; N] {
     type Read = [I::Read; N];
-    type Write = [I::Write; N];
+    type Write<'b>
+        = [I::Write<'b>; N]
+    where
+        Self: 'b;
+
+    const EXCLUSIVE: bool = I::EXCLUSIVE;
 
     fn read(self) -> Self::Read {
         self.map(I::read)
     }
 
-    fn write(self) -> Self::Write {
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b,
+    {
         self.map(I::write)
     }
+
+    fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+        self.iter().for_each(|item| item.borrows(borrows));
+    }
 }
 
 impl<I: Item> Item for Option<I> {
     type Read = Option<I::Read>;
-    type Write = Option<I::Write>;
+    type Write<'b>
+        = Option<I::Write<'b>>
+    where
+        Self: 'b;
+
+    const EXCLUSIVE: bool = I::EXCLUSIVE;
 
     fn read(self) -> Self::Read {
         self.map(I::read)
     }
 
-    fn write(self) -> Self::Write {
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b,
+    {
         self.map(I::write)
     }
+
+    fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+        if let Some(item) = self {
+            item.borrows(borrows);
+        }
+    }
+}
+
+impl Item for () {
+    type Read = Self;
+    type Write<'b> = Self;
+
+    fn read(self) -> Self::Read {
+        self
+    }
+    fn write<'b>(self) -> Self::Write<'b> {
+        self
+    }
+}
+
+/// The empty view, which borrows no field.
+unsafe impl<T> View<'_, T> for () {
+    const READS: u64 = 0;
+    const WRITES: u64 = 0;
+
+    #[inline]
+    unsafe fn view(_: *mut T) -> Self {}
 }
 
 impl Item for Key {
     type Read = Self;
-    type Write = Self;
+    type Write<'b> = Self;
 
     fn read(self) -> Self::Read {
         self
     }
-    fn write(self) -> Self::Write {
+    fn write<'b>(self) -> Self::Write<'b> {
         self
     }
 }
 
-#[inline]
-pub fn fork<'a, T, L: Item, R: Item>(
-    slice: &'a mut [T],
-    fork: impl Fn(&'a mut T) -> (L, R) + Copy,
-) -> (
-    Fork<'a, T, impl Fn(&'a mut T) -> L>,
-    Fork<'a, T, impl Fn(&'a mut T) -> R>,
-) {
-    let data = slice.as_mut_ptr();
-    let count = slice.len();
-    let left = Fork(data, count, move |item| fork(item).0, PhantomData);
-    let right = Fork(data, count, move |item| fork(item).1, PhantomData);
-    (left, right)
+macro_rules! fork {
+    ($($name:ident($($item:ident $project:ident),+);)*) => {$(
+        /// Splits the elements of `slice` into one [`Fork`] per projection. Projections receive a pointer to an element
+        /// rather than a reference, such that they only borrow the fields they project and never the whole element.
+        #[inline]
+        #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+        pub fn $name<'a, T, $($item: Item),+>(
+            slice: &'a mut [T],
+            lookup: Lookup<'a>,
+            key: fn(&T) -> Key,
+            $($project: impl Fn(*mut T) -> $item + Copy + 'a,)+
+        ) -> ($(Fork<'a, T, impl Fn(*mut T) -> $item>,)+) {
+            let data = slice.as_mut_ptr();
+            let count = slice.len();
+            validate(data, count, key, |item| {
+                let mut views = Vec::new();
+                $({
+                    let mut borrows = Vec::new();
+                    $project(item).borrows(&mut borrows);
+                    views.push(borrows);
+                })+
+                views
+            });
+            ($(Fork(data, count, $project, lookup, None, PhantomData),)+)
+        }
+    )*};
+}
+
+fork! {
+    fork(L left, R right);
+    fork3(I1 first, I2 second, I3 third);
+    fork4(I1 first, I2 second, I3 third, I4 fourth);
+    fork5(I1 first, I2 second, I3 third, I4 fourth, I5 fifth);
+    fork6(I1 first, I2 second, I3 third, I4 fourth, I5 fifth, I6 sixth);
+}
+
+impl<'a, S, F> Fork<'a, S, F> {
+    /// # Safety
+    /// `data` must be valid for `count` elements for `'a` and the items of `project` must not conflict with how the
+    /// elements are borrowed elsewhere. The items are not validated.
+    #[inline]
+    pub(crate) unsafe fn new(data: *mut S, count: usize, project: F, lookup: Lookup<'a>) -> Self {
+        Fork(data, count, project, lookup, None, PhantomData)
+    }
 }
 
-impl<'a, S: 'static, T: Item, F: Fn(&'a mut S) -> T> Fork<'a, S, F> {
-    pub fn iter_mut(&mut self) -> impl FullIterator<Item = T::Write> + '_ {
-        unsafe { from_raw_parts_mut(self.0, self.1) }
-            .iter_mut()
-            .map(|item| self.2(item).write())
+impl<'a, S, T: Item, F: Project<S, Item = T>> Fork<'a, S, F> {
+    /// Yields the mutable views of the elements, which can not outlive the borrow of the fork.
+    pub fn iter_mut<'b>(&'b mut self) -> impl FullIterator<Item = T::Write<'b>> + 'b
+    where
+        T: 'b,
+    {
+        let fork = &*self;
+        (0..fork.count()).map(move |at| fork.project(fork.position(at)).write())
     }
 
+    /// Fails to compile if the items of the fork borrow anything exclusively.
     pub fn iter(&self) -> impl FullIterator<Item = T::Read> + '_ {
-        unsafe { from_raw_parts_mut(self.0, self.1) }
-            .iter_mut()
-            .map(|item| self.2(item).read())
+        const { assert!(!T::EXCLUSIVE, "{}", SHARED) };
+        (0..self.count()).map(move |at| self.project(self.position(at)).read())
+    }
+
+    /// Gets the view of the value of `key`. Fails to compile if the items of the fork borrow anything exclusively.
+    pub fn get<K: KeyType>(&self, key: K) -> Option<T::Read> {
+        const { assert!(!T::EXCLUSIVE, "{}", SHARED) };
+        Some(self.project(self.find(key)?).read())
+    }
+
+    /// Gets the mutable view of the value of `key`, which can not outlive the borrow of the fork.
+    ///
+    /// ```compile_fail
+    /// let mut armoire = armoire::Armoire::new();
+    /// let key = armoire.insert(0);
+    /// let (mut values, _) = armoire.fork(|_, value| unsafe { &mut *value }, |key, _| key);
+    /// let first = values.get_mut(key).unwrap();
+    /// let second = values.get_mut(key).unwrap();
+    /// *first += *second;
+    /// ```
+    pub fn get_mut<'b, K: KeyType>(&'b mut self, key: K) -> Option<T::Write<'b>>
+    where
+        T: 'b,
+    {
+        Some(self.project(self.find(key)?).write())
+    }
+
+    /// The number of elements yielded by the fork.
+    #[inline]
+    fn count(&self) -> usize {
+        self.4.as_ref().map_or(self.1, |positions| positions.len())
+    }
+
+    /// The position in the slice of the `at`-th element yielded by the fork.
+    #[inline]
+    fn position(&self, at: usize) -> usize {
+        self.4
+            .as_ref()
+            .map_or(at, |positions| positions[at] as usize)
+    }
+
+    #[inline]
+    fn find<K: KeyType>(&self, key: K) -> Option<usize> {
+        let position = self.3.find(key)?;
+        match &self.4 {
+            Some(positions) => positions
+                .binary_search(&(position as u32))
+                .ok()
+                .map(|_| position),
+            None => Some(position),
+        }
+    }
+
+    #[inline]
+    fn project(&self, position: usize) -> T {
+        assert!(position < self.1);
+        unsafe { self.2.project(self.0.add(position)) }
     }
 }
 
+impl<'a, S, T: Item, F: Project<S, Item = Option<T>>> Fork<'a, S, F> {
+    /// Only yields the elements whose item is `Some`. The matching elements are found once, here, such that iterating
+    /// the filtered fork does not evaluate the items of the other elements again. The projection should keep returning
+    /// `Some` for the elements that it matched here, which holds as long as it only decides on what the forks do not
+    /// write, such as the variant of an enum.
+    ///
+    /// # Panics
+    /// When iterated, if the item of a matching element is no longer `Some`.
+    pub fn filter(self) -> Fork<'a, S, impl Fn(*mut S) -> T> {
+        let positions = (0..self.count())
+            .map(|at| self.position(at))
+            .filter(|&position| self.project(position).is_some())
+            .map(|position| position as u32)
+            .collect();
+        let Self(data, count, project, lookup, ..) = self;
+        let project = move |item| {
+            unsafe { project.project(item) }
+                .expect("the item of a filtered element must remain `Some`")
+        };
+        Fork(data, count, project, lookup, Some(positions), PhantomData)
+    }
+}
+
+const SHARED: &str = "only the forks of shared items can be accessed through a shared reference";
+
+// SAFETY: Through a shared reference, a fork only projects its elements with `iter`, `get` and `par_iter`, which fail
+// to compile for items that borrow anything exclusively, such that threads only share the elements like a `&[S]` would.
+// `par_iter_mut` shares the fork across threads too, but projects every element exactly once, and requires `S: Send`.
 unsafe impl<S: Sync, F: Sync> Sync for Fork<'_, S, F> {}
 
-impl<'a, S: Send + Sync + 'static, T: Item, F: Fn(&'a mut S) -> T + Sync> Fork<'a, S, F>
+impl<'a, S: Send + Sync, T: Item, F: Project<S, Item = T> + Sync> Fork<'a, S, F>
 where
     T::Read: Send,
-    T::Write: Send,
 {
-    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = T::Write> + '_ {
-        unsafe { from_raw_parts_mut(self.0, self.1) }
-            .par_iter_mut()
-            .map(|item| self.2(item).write())
+    /// Same as [`Self::iter_mut`], but in parallel.
+    pub fn par_iter_mut<'b>(&'b mut self) -> impl IndexedParallelIterator<Item = T::Write<'b>> + 'b
+    where
+        T: 'b,
+        T::Write<'b>: Send,
+    {
+        let fork = &*self;
+        (0..fork.count())
+            .into_par_iter()
+            .map(move |at| fork.project(fork.position(at)).write())
     }
 
+    /// Fails to compile if the items of the fork borrow anything exclusively.
     pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = T::Read> + '_ {
-        unsafe { from_raw_parts_mut(self.0, self.1) }
-            .par_iter_mut()
-            .map(|item| self.2(item).read())
+        const { assert!(!T::EXCLUSIVE, "{}", SHARED) };
+        (0..self.count())
+            .into_par_iter()
+            .map(move |at| self.project(self.position(at)).read())
     }
 }
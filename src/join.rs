@@ -0,0 +1,376 @@
+use crate::{index, tick::Clock, Armoire, Key, KeyType, Slot, Tag};
+use rayon::prelude::*;
+use std::{collections::HashMap, iter::FusedIterator, marker::PhantomData};
+
+/// A collection that takes part in a [`Join`]. See [`IntoSource`] for the collections that can be joined.
+///
+/// # Safety
+/// The keys at distinct positions below [`Source::count`] must be distinct, and the items that [`Source::get`] yields
+/// for distinct keys must not alias, since a [`Join`] yields the items of every position at once.
+pub unsafe trait Source {
+    type Key: KeyType;
+    /// The item yielded by the join for a key.
+    type Item;
+
+    /// The number of values of the source. The join walks the pairs of the source with the fewest values.
+    fn size(&self) -> usize;
+    /// The number of positions of [`Source::key`].
+    fn count(&self) -> usize;
+    /// The key at `position` in the dense pairs of the source.
+    fn key(&self, position: usize) -> Self::Key;
+    /// # Safety
+    /// The item of a key must not be alive when `get` is called again with the same key.
+    unsafe fn get(&self, key: Self::Key) -> Option<Self::Item>;
+}
+
+/// Converts a reference to a collection into a [`Source`]. A shared reference yields shared references to its values
+/// and an exclusive reference yields exclusive references to its values.
+pub trait IntoSource {
+    type Source: Source;
+    fn into_source(self) -> Self::Source;
+}
+
+/// The sources of a [`Join`]. Implemented for tuples of up to 6 [`Source`]s that share a key type.
+///
+/// # Safety
+/// The items that [`Sources::get`] yields for distinct positions of the same driver must not alias.
+pub unsafe trait Sources {
+    type Item;
+
+    /// The index of the source that drives the join.
+    fn driver(&self) -> usize;
+    /// # Safety
+    /// See [`Source::get`].
+    unsafe fn get(&self, driver: usize, position: usize) -> Option<Self::Item>;
+    fn count(&self, driver: usize) -> usize;
+}
+
+/// Shared access to the values of a collection.
+pub struct Ref<'a, K, V> {
+    lookup: Lookup<'a>,
+    pairs: &'a [(K, V)],
+}
+
+/// Exclusive access to the values of a collection. The values of an [`Armoire`] that tracks changes are marked as
+/// changed when they are yielded.
+pub struct Mut<'a, K, V> {
+    lookup: Lookup<'a>,
+    pairs: *mut (K, V),
+    count: usize,
+    clock: Clock<'a>,
+    _marker: PhantomData<&'a mut [(K, V)]>,
+}
+
+/// Joins a source without requiring its values. See [`maybe`].
+pub struct Maybe<S>(S);
+
+/// An iterator over the keys that have a value in every source of a join. See [`crate::join!`].
+pub struct Join<S> {
+    sources: S,
+    driver: usize,
+    position: usize,
+    count: usize,
+}
+
+/// Resolves key indices to the position of their pair.
+#[derive(Clone, Copy)]
+pub(crate) enum Lookup<'a> {
+    Slots(&'a [Slot], Tag),
+    Indices(&'a [u32]),
+    Map(&'a HashMap<u32, u32>),
+}
+
+/// Yields the values of the keys that have a value in every source, along with their key. Sources are references to
+/// an [`Armoire`], a [`crate::SecondaryMap`] or a [`crate::SparseSecondaryMap`], where shared references yield shared
+/// references to values and exclusive references yield exclusive references to values. Wrap a source with [`maybe`] to
+/// yield an `Option` of its value instead of requiring it. The join walks the pairs of the required source with the
+/// fewest values and looks up the other sources by key. Use [`Join::into_par_iter`] to iterate in parallel.
+///
+/// ```
+/// use armoire::{join, maybe, Armoire, SecondaryMap};
+///
+/// let mut positions = Armoire::new();
+/// let mut velocities = SecondaryMap::new();
+/// let mut names = SecondaryMap::new();
+/// let [a, b] = positions.insert_n([0, 10]);
+/// velocities.insert(a, 1).unwrap();
+/// velocities.insert(b, 2).unwrap();
+/// names.insert(b, "b").unwrap();
+/// for (_, position, velocity, name) in join!(&mut positions, &velocities, maybe(&names)) {
+///     *position += velocity;
+///     assert_eq!(name.is_some(), *position == 12);
+/// }
+/// assert_eq!(positions.get(a), Some(&1));
+/// ```
+#[macro_export]
+macro_rules! join {
+    ($($source:expr),+ $(,)?) => {
+        $crate::__join(($($crate::IntoSource::into_source($source),)+))
+    };
+}
+
+/// Joins `source` without requiring its values, such that a [`Join`] yields `None` for the keys that it is missing.
+#[inline]
+pub fn maybe<S: IntoSource>(source: S) -> Maybe<S::Source> {
+    Maybe(source.into_source())
+}
+
+/// Constructs the [`Join`] of [`crate::join!`], which is otherwise only constructed within this crate.
+#[doc(hidden)]
+#[inline]
+pub fn __join<S: Sources>(sources: S) -> Join<S> {
+    Join::new(sources)
+}
+
+impl Lookup<'_> {
+    #[inline]
+    pub fn find<K: KeyType>(&self, key: K) -> Option<usize> {
+        let key: Key = key.into();
+        match self {
+            Lookup::Slots(slots, tag) => index(key, slots, *tag),
+            Lookup::Indices(slots) => slots
+                .get(key.index as usize)
+                .filter(|&&position| position < u32::MAX)
+                .map(|&position| position as usize),
+            Lookup::Map(slots) => slots.get(&key.index).map(|&position| position as usize),
+        }
+    }
+}
+
+impl<'a, K, V> Ref<'a, K, V> {
+    #[inline]
+    pub(crate) fn new(lookup: Lookup<'a>, pairs: &'a [(K, V)]) -> Self {
+        Self { lookup, pairs }
+    }
+}
+
+impl<'a, K, V> Mut<'a, K, V> {
+    #[inline]
+    pub(crate) fn new(lookup: Lookup<'a>, pairs: &'a mut [(K, V)], clock: Clock<'a>) -> Self {
+        Self {
+            lookup,
+            count: pairs.len(),
+            pairs: pairs.as_mut_ptr(),
+            clock,
+            _marker: PhantomData,
+        }
+    }
+}
+
+unsafe impl<'a, K: KeyType, V> Source for Ref<'a, K, V> {
+    type Key = K;
+    type Item = &'a V;
+
+    #[inline]
+    fn size(&self) -> usize {
+        self.pairs.len()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    #[inline]
+    fn key(&self, position: usize) -> K {
+        self.pairs[position].0
+    }
+
+    #[inline]
+    unsafe fn get(&self, key: K) -> Option<Self::Item> {
+        let (stored, value) = &self.pairs[self.lookup.find(key)?];
+        (*stored == key).then_some(value)
+    }
+}
+
+unsafe impl<'a, K: KeyType, V> Source for Mut<'a, K, V> {
+    type Key = K;
+    type Item = &'a mut V;
+
+    #[inline]
+    fn size(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    fn key(&self, position: usize) -> K {
+        assert!(position < self.count);
+        // Only the key is read, such that the value may be borrowed elsewhere.
+        unsafe { (*self.pairs.add(position)).0 }
+    }
+
+    #[inline]
+    unsafe fn get(&self, key: K) -> Option<Self::Item> {
+        let position = self.lookup.find(key)?;
+        assert!(position < self.count);
+        let pair = self.pairs.add(position);
+        if (*pair).0 == key {
+            self.clock.change(key.into());
+            Some(&mut (*pair).1)
+        } else {
+            None
+        }
+    }
+}
+
+unsafe impl<K: Sync, V: Send + Sync> Sync for Mut<'_, K, V> {}
+unsafe impl<K: Send, V: Send> Send for Mut<'_, K, V> {}
+
+unsafe impl<S: Source> Source for Maybe<S> {
+    type Key = S::Key;
+    type Item = Option<S::Item>;
+
+    /// An optional source only drives the join if every source is optional.
+    #[inline]
+    fn size(&self) -> usize {
+        usize::MAX
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.0.count()
+    }
+
+    #[inline]
+    fn key(&self, position: usize) -> S::Key {
+        self.0.key(position)
+    }
+
+    #[inline]
+    unsafe fn get(&self, key: S::Key) -> Option<Self::Item> {
+        Some(self.0.get(key))
+    }
+}
+
+impl<S: Source> IntoSource for Maybe<S> {
+    type Source = Self;
+
+    #[inline]
+    fn into_source(self) -> Self::Source {
+        self
+    }
+}
+
+impl<'a, T, K: KeyType> IntoSource for &'a Armoire<T, K> {
+    type Source = Ref<'a, K, T>;
+
+    #[inline]
+    fn into_source(self) -> Self::Source {
+        Ref::new(Lookup::Slots(&self.slots, self.tag), &self.pairs)
+    }
+}
+
+impl<'a, T, K: KeyType> IntoSource for &'a mut Armoire<T, K> {
+    type Source = Mut<'a, K, T>;
+
+    #[inline]
+    fn into_source(self) -> Self::Source {
+        self.track();
+        Mut::new(
+            Lookup::Slots(&self.slots, self.tag),
+            &mut self.pairs,
+            Clock::new(&self.ticks, self.tick),
+        )
+    }
+}
+
+impl<S: Sources> Join<S> {
+    #[inline]
+    pub(crate) fn new(sources: S) -> Self {
+        let driver = sources.driver();
+        Self {
+            count: sources.count(driver),
+            sources,
+            driver,
+            position: 0,
+        }
+    }
+
+    /// Same as iterating the join, but in parallel.
+    pub fn into_par_iter(self) -> impl ParallelIterator<Item = S::Item>
+    where
+        S: Send + Sync,
+        S::Item: Send,
+    {
+        let Self {
+            sources,
+            driver,
+            position,
+            count,
+        } = self;
+        // Each position of the driver holds a distinct key, such that the items of each position are distinct.
+        (position..count)
+            .into_par_iter()
+            .filter_map(move |position| unsafe { sources.get(driver, position) })
+    }
+}
+
+impl<S: Sources> Iterator for Join<S> {
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position < self.count {
+            let position = self.position;
+            self.position += 1;
+            // Each position of the driver holds a distinct key and is visited once.
+            if let Some(item) = unsafe { self.sources.get(self.driver, position) } {
+                return Some(item);
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.count - self.position))
+    }
+}
+
+impl<S: Sources> FusedIterator for Join<S> {}
+
+macro_rules! sources {
+    ($($source:ident $index:tt),+) => {
+        // Each position of the driver holds a distinct key, whose items do not alias in any source.
+        unsafe impl<K: KeyType, $($source: Source<Key = K>),+> Sources for ($($source,)+) {
+            type Item = (K, $($source::Item,)+);
+
+            #[inline]
+            fn driver(&self) -> usize {
+                let sizes = [$(self.$index.size()),+];
+                (0..sizes.len())
+                    .min_by_key(|&index| sizes[index])
+                    .unwrap_or_default()
+            }
+
+            #[inline]
+            fn count(&self, driver: usize) -> usize {
+                match driver {
+                    $($index => self.$index.count(),)+
+                    _ => unreachable!(),
+                }
+            }
+
+            #[inline]
+            unsafe fn get(&self, driver: usize, position: usize) -> Option<Self::Item> {
+                let key = match driver {
+                    $($index => self.$index.key(position),)+
+                    _ => unreachable!(),
+                };
+                Some((key, $(self.$index.get(key)?,)+))
+            }
+        }
+    };
+}
+
+sources!(S0 0);
+sources!(S0 0, S1 1);
+sources!(S0 0, S1 1, S2 2);
+sources!(S0 0, S1 1, S2 2, S3 3);
+sources!(S0 0, S1 1, S2 2, S3 3, S4 4);
+sources!(S0 0, S1 1, S2 2, S3 3, S4 4, S5 5);
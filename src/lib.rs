@@ -1,20 +1,125 @@
+mod entry;
+mod error;
+mod event;
 mod fork;
+mod join;
+mod log;
+mod queue;
+mod schedule;
+mod secondary;
+mod table;
+mod tick;
 mod utility;
 
-use fork::{Fork, Item};
-use parking_lot::Mutex;
+pub use armoire_derive::Fork;
+use entry::Observers;
+pub use entry::{Entry, OccupiedEntry, StaleEntry, VacantEntry};
+pub use error::{Error, InsertError};
+pub use event::Event;
+pub use fork::{Fork, Item, Project, View};
+use itertools::Either;
+use join::Lookup;
+pub use join::{__join, maybe, IntoSource, Join, Maybe, Mut, Ref, Source, Sources};
+use log::Log;
+pub use log::Reader;
+use queue::Queue;
 use rayon::prelude::*;
+pub use schedule::{Access, Schedule, System, Tracking};
+pub use secondary::{SecondaryMap, SparseSecondaryMap};
 use std::{
-    collections::HashSet,
-    mem::replace,
-    sync::atomic::{AtomicI64, AtomicU32, Ordering},
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    marker::PhantomData,
+    mem::{replace, transmute},
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering},
+    vec,
 };
+pub use table::{Row, Table, TableDefer};
+pub use tick::Tracked;
+use tick::{Clock, Ticks};
 use utility::FullIterator;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Key {
     generation: u32,
     index: u32,
+    tag: Tag,
+}
+
+/// Identifies the [`Armoire`] that reserved a [`Key`]. With the `tag` feature, each armoire stamps its own tag into the
+/// keys it reserves and rejects keys that carry another tag. Without it, this type is zero-sized and never rejects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Tag(#[cfg(feature = "tag")] u32);
+
+/// A key that can be used to index an [`Armoire`]. Keys of different types can not be mixed, such that a key handed out
+/// by an `Armoire<T, K1>` can not be used with an `Armoire<U, K2>`. Declare new key types with [`new_key_type`].
+///
+/// ```compile_fail
+/// armoire::new_key_type! {
+///     struct EntityKey;
+///     struct AssetKey;
+/// }
+/// let mut entities = armoire::Armoire::<&str, EntityKey>::with_key();
+/// let assets = armoire::Armoire::<&str, AssetKey>::with_key();
+/// let key = entities.insert("player");
+/// assets.get(key);
+/// ```
+pub trait KeyType: Copy + Eq + Ord + Hash + Debug + From<Key> + Into<Key> {}
+
+impl KeyType for Key {}
+
+/// Declares one or more key types to be used with [`Armoire::with_key`].
+///
+/// ```
+/// armoire::new_key_type! {
+///     pub struct EntityKey;
+///     struct AssetKey;
+/// }
+/// let mut entities = armoire::Armoire::<&str, EntityKey>::with_key();
+/// let key: EntityKey = entities.insert("player");
+/// assert_eq!(entities.get(key), Some(&"player"));
+/// ```
+#[macro_export]
+macro_rules! new_key_type {
+    ($($(#[$meta:meta])* $visibility:vis struct $name:ident;)*) => {$(
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        $visibility struct $name($crate::Key);
+
+        impl From<$crate::Key> for $name {
+            #[inline]
+            fn from(key: $crate::Key) -> Self {
+                Self(key)
+            }
+        }
+
+        impl From<$name> for $crate::Key {
+            #[inline]
+            fn from(key: $name) -> Self {
+                key.0
+            }
+        }
+
+        impl $crate::KeyType for $name {}
+
+        impl $crate::Item for $name {
+            type Read = Self;
+            type Write<'b> = Self;
+
+            #[inline]
+            fn read(self) -> Self::Read {
+                self
+            }
+
+            #[inline]
+            fn write<'b>(self) -> Self::Write<'b> {
+                self
+            }
+        }
+    )*};
 }
 
 #[derive(Clone, Copy)]
@@ -23,31 +128,206 @@ struct Slot {
     index: u32,
 }
 
-type Pair<T> = (Key, T);
+type Pair<T, K = Key> = (K, T);
+
+/// A deferred change to the value of a key.
+enum Change<T> {
+    Modify(Box<dyn FnOnce(&mut T) + Send>),
+    Replace(T),
+}
+
+/// A callback that observes the value of a key as it is inserted or removed.
+type Hook<T, K> = Box<dyn FnMut(K, &mut T, &Defer<T, K>) + Send + Sync>;
+
+/// Builds the [`Defer`] of an armoire from its fields, such that its other fields can be borrowed at the same time.
+macro_rules! defer {
+    ($armoire:expr) => {
+        Defer {
+            tag: $armoire.tag,
+            conflict: $armoire.conflict,
+            sequence: &$armoire.sequence,
+            cursor: &$armoire.cursor,
+            free: &$armoire.free,
+            last: &$armoire.last,
+            inserts: &$armoire.inserts,
+            changes: &$armoire.changes,
+            removes: &$armoire.removes,
+            bound: PhantomData,
+        }
+    };
+}
+
+/// Generates the methods that split each value into one item per [`struct@Fork`], for types with a `parts` method that
+/// returns their pairs, a lookup of their keys and their clock.
+macro_rules! forks {
+    () => {
+        forks! {
+            /// Splits each value into the items projected by `left` and `right`, each of which can be iterated
+            /// independently of the other. Projections receive a pointer to the value rather than a reference, such
+            /// that each fork only borrows the fields that it projects: borrow fields through place expressions such as
+            /// `&mut (*value).field` or `ref mut` patterns, never the whole value of a field that the other projection
+            /// borrows. Matching on an enum reads its variant, which may be stored in a niche of one of its fields:
+            /// give such enums a `#[repr(u8)]`, which stores the variant in a tag of its own, if a projection writes to
+            /// the field. Debug builds panic if the items of the projections overlap. See [`Self::fork_by`] for a safe
+            /// alternative.
+            fork(L left, R right);
+            /// Same as [`Self::fork`], but splits each value into 3 items.
+            fork3(I1 first, I2 second, I3 third);
+            /// Same as [`Self::fork`], but splits each value into 4 items.
+            fork4(I1 first, I2 second, I3 third, I4 fourth);
+            /// Same as [`Self::fork`], but splits each value into 5 items.
+            fork5(I1 first, I2 second, I3 third, I4 fourth, I5 fifth);
+            /// Same as [`Self::fork`], but splits each value into 6 items.
+            fork6(I1 first, I2 second, I3 third, I4 fourth, I5 fifth, I6 sixth);
+        }
+
+        /// Same as [`Self::fork`], but splits each value into the views `L` and `R` generated with `#[derive(Fork)]`.
+        /// Fails to compile if a field is borrowed exclusively by one view and borrowed at all by the other.
+        #[inline]
+        #[allow(clippy::type_complexity)]
+        pub fn fork_by<'a, L: View<'a, T>, R: View<'a, T>>(
+            &'a mut self,
+        ) -> (
+            Fork<'a, Pair<T, K>, impl Fn(*mut Pair<T, K>) -> Tracked<'a, L>>,
+            Fork<'a, Pair<T, K>, impl Fn(*mut Pair<T, K>) -> Tracked<'a, R>>,
+        ) {
+            const {
+                assert!(
+                    L::WRITES & (R::READS | R::WRITES) == 0 && R::WRITES & L::READS == 0,
+                    "the views of a fork must not borrow the same field mutably"
+                )
+            };
+            self.fork(
+                |_, value| unsafe { L::view(value) },
+                |_, value| unsafe { R::view(value) },
+            )
+        }
+
+        /// Same as [`Self::fork`], but each fork only yields the values for which its projection returns `Some`. The
+        /// matching values are found once, such that iterating the forks again does not project the other values. See
+        /// [`Fork::filter`].
+        #[inline]
+        #[allow(clippy::type_complexity)]
+        pub fn fork_filter<'a, L: Item, R: Item>(
+            &'a mut self,
+            left: impl Fn(K, *mut T) -> Option<L> + Copy + 'a,
+            right: impl Fn(K, *mut T) -> Option<R> + Copy + 'a,
+        ) -> (
+            Fork<'a, Pair<T, K>, impl Fn(*mut Pair<T, K>) -> Tracked<'a, L>>,
+            Fork<'a, Pair<T, K>, impl Fn(*mut Pair<T, K>) -> Tracked<'a, R>>,
+        ) {
+            let (pairs, lookup, clock) = self.parts();
+            let key = |pair: &Pair<T, K>| pair.0.into();
+            let (left, right) = fork::fork(
+                pairs,
+                lookup,
+                key,
+                filtered(clock, left),
+                filtered(clock, right),
+            );
+            (left.filter(), right.filter())
+        }
+    };
+    ($($(#[$meta:meta])* $name:ident($($item:ident $project:ident),+);)*) => {$(
+        $(#[$meta])*
+        #[inline]
+        #[allow(clippy::type_complexity)]
+        pub fn $name<'a, $($item: Item),+>(
+            &'a mut self,
+            $($project: impl Fn(K, *mut T) -> $item + Copy + 'a,)+
+        ) -> ($(Fork<'a, Pair<T, K>, impl Fn(*mut Pair<T, K>) -> Tracked<'a, $item>>,)+) {
+            let (pairs, lookup, clock) = self.parts();
+            let key = |pair: &Pair<T, K>| pair.0.into();
+            fork::$name(pairs, lookup, key, $(move |pair: *mut Pair<T, K>| {
+                let key = unsafe { (*pair).0 };
+                clock.track(key.into(), $project(key, unsafe { &raw mut (*pair).1 }))
+            }),+)
+        }
+    )*};
+}
+
+/// The order in which [`Armoire::resolve`] applies deferred operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Order {
+    /// Operations deferred from the same thread are applied in the order they were deferred, but operations deferred
+    /// from different threads are applied in an unspecified order.
+    #[default]
+    Arrival,
+    /// Operations are applied sorted by key, such that the resulting layout of the pairs only depends on the deferred
+    /// operations and not on the timing of the threads that deferred them. Changes deferred for the same key from
+    /// different threads are still applied in an unspecified order. Note that keys reserved concurrently through
+    /// [`Defer::insert`] are handed out in an unspecified order; reserve them up front and use [`Defer::try_insert`]
+    /// instead for a fully deterministic outcome.
+    Sorted,
+}
+
+/// How [`Armoire::resolve`] settles deferred inserts and removes of the same key. With either policy, removing a
+/// reserved key that has no value releases it, after which inserts at that key are rejected as [`Error::Stale`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Conflict {
+    /// Every deferred insert is applied before every deferred remove, such that a key that is removed stays removed
+    /// regardless of the order of the operations. Inserts at an occupied key are rejected as [`Error::Occupied`].
+    #[default]
+    RemoveWins,
+    /// Deferred inserts and removes of the same key take effect in the order they were deferred, such that the last
+    /// one decides whether the key has a value. A remove followed by an insert at the same key keeps the key valid and
+    /// an insert at an occupied key replaces its value. Intermediate values of a key that is inserted more than once
+    /// may be reported as [`Event::Replaced`] rather than [`Event::Removed`].
+    LastWins,
+}
 
-pub struct Armoire<T> {
+pub struct Armoire<T, K = Key> {
+    tag: Tag,
+    order: Order,
+    conflict: Conflict,
+    sequence: AtomicU64,
+    tracked: bool,
+    tick: u32,
+    /// The ticks of every slot when change tracking is enabled, otherwise empty.
+    ticks: Vec<Ticks>,
+    log: Option<Log<T, K>>,
+    on_insert: Vec<Hook<T, K>>,
+    on_remove: Vec<Hook<T, K>>,
     last: AtomicU32,
     cursor: AtomicI64,
     slots: Vec<Slot>,
     free: Vec<Key>,
-    pairs: Vec<Pair<T>>,
-    inserts: Mutex<Vec<Pair<T>>>,
-    removes: Mutex<HashSet<Key>>,
+    /// Keys that were freed while `free` was shared with a [`Defer`]. They are merged into `free` on the next removal,
+    /// release or resolve.
+    pending: Vec<Key>,
+    pairs: Vec<Pair<T, K>>,
+    inserts: Queue<(Pair<T, K>, u64)>,
+    changes: Queue<Pair<Change<T>, K>>,
+    removes: Queue<(K, u64)>,
 }
 
-pub struct Pairs<'a, T> {
+pub struct Pairs<'a, T, K = Key> {
+    tag: Tag,
+    last: &'a AtomicU32,
     slots: &'a mut Vec<Slot>,
-    pairs: &'a mut Vec<Pair<T>>,
+    pending: &'a mut Vec<Key>,
+    pairs: &'a mut Vec<Pair<T, K>>,
+    clock: Clock<'a>,
+    observers: Observers<'a, T, K>,
 }
 
-pub struct Defer<'a, T> {
+pub struct Defer<'a, T, K = Key, B = Bound<'static>> {
+    tag: Tag,
+    conflict: Conflict,
+    sequence: &'a AtomicU64,
     last: &'a AtomicU32,
     cursor: &'a AtomicI64,
     free: &'a Vec<Key>,
-    inserts: &'a Mutex<Vec<Pair<T>>>,
-    removes: &'a Mutex<HashSet<Key>>,
+    inserts: &'a Queue<(Pair<T, K>, u64)>,
+    changes: &'a Queue<Pair<Change<T>, K>>,
+    removes: &'a Queue<(K, u64)>,
+    bound: PhantomData<B>,
 }
 
+/// The lifetime `'a` that outlives the closures deferred through a [`Defer`] with [`Defer::modify`]. It is `'static`,
+/// except for the [`Defer`] of [`Armoire::scope`], which resolves the deferred operations before returning.
+pub struct Bound<'a>(PhantomData<fn(&'a ()) -> &'a ()>);
+
 impl Slot {
     pub const ZERO: Slot = Slot::new(0, u32::MAX);
 
@@ -79,6 +359,29 @@ impl Slot {
         }
     }
 
+    /// Same as [`Slot::release`], but keeps the generation such that the key can receive a value again.
+    #[inline]
+    pub fn vacate(&mut self, generation: u32) -> Option<u32> {
+        debug_assert!(generation < u32::MAX);
+        if self.generation == generation && self.index < u32::MAX {
+            Some(replace(&mut self.index, u32::MAX))
+        } else {
+            None
+        }
+    }
+
+    /// Invalidates the reserved key of a slot that has no value.
+    #[inline]
+    pub fn discard(&mut self, generation: u32) -> bool {
+        debug_assert!(generation < u32::MAX);
+        if self.generation == generation && self.index == u32::MAX {
+            self.generation = self.generation.saturating_add(1);
+            true
+        } else {
+            false
+        }
+    }
+
     #[inline]
     pub fn update(&mut self, index: u32) -> bool {
         debug_assert!(index < u32::MAX);
@@ -92,46 +395,135 @@ impl Slot {
 }
 
 impl Key {
-    pub const NULL: Key = Key::new(u32::MAX, u32::MAX);
+    pub const NULL: Key = Key::new(u32::MAX, u32::MAX, Tag::NULL);
 
     #[inline]
-    pub(crate) const fn new(generation: u32, index: u32) -> Self {
-        Self { generation, index }
+    pub(crate) const fn new(generation: u32, index: u32, tag: Tag) -> Self {
+        Self {
+            generation,
+            index,
+            tag,
+        }
     }
 
     #[inline]
     pub(crate) fn increment(self) -> Option<Key> {
-        Some(Key::new(self.generation.checked_add(1)?, self.index))
+        Some(Key::new(
+            self.generation.checked_add(1)?,
+            self.index,
+            self.tag,
+        ))
+    }
+}
+
+impl Tag {
+    #[cfg(feature = "tag")]
+    pub const NULL: Tag = Tag(u32::MAX);
+    #[cfg(not(feature = "tag"))]
+    pub const NULL: Tag = Tag();
+
+    #[cfg(feature = "tag")]
+    pub fn new() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let tag = NEXT.fetch_add(1, Ordering::Relaxed);
+        assert!(tag < u32::MAX, "too many armoires have been created");
+        Tag(tag)
+    }
+
+    #[cfg(not(feature = "tag"))]
+    pub fn new() -> Self {
+        Tag()
+    }
+
+    /// Returns `true` if the `key` was reserved by the armoire that owns this tag. With the `tag-panic` feature and
+    /// debug assertions enabled, a key that carries another armoire's tag panics instead.
+    #[inline]
+    pub fn accepts(self, key: Key) -> bool {
+        let accept = self == key.tag;
+        if cfg!(all(feature = "tag-panic", debug_assertions)) && !accept && key.tag != Tag::NULL {
+            panic!("key '{key:?}' was reserved by another armoire (expected tag '{self:?}')");
+        }
+        accept
     }
 }
 
-impl<'a, T> Defer<'a, T> {
+impl<'a, T, K: KeyType, B> Defer<'a, T, K, B> {
+    /// Reserves a key and defers the insertion of `value` until the next resolve.
+    ///
+    /// # Panics
+    /// If key indices are exhausted.
     #[inline]
-    pub fn insert(&self, value: T) -> Key {
+    pub fn insert(&self, value: T) -> K {
         let [key] = self.insert_n([value]);
         key
     }
 
+    /// Reserves `N` keys and defers the insertion of `values` until the next resolve.
+    ///
+    /// # Panics
+    /// If key indices are exhausted.
     #[inline]
-    pub fn insert_n<const N: usize>(&self, values: [T; N]) -> [Key; N] {
-        let mut keys = [Key::NULL; N];
-        reserve(&mut keys, self.cursor, self.free, self.last);
-        self.inserts.lock().extend(keys.iter().copied().zip(values));
+    pub fn insert_n<const N: usize>(&self, values: [T; N]) -> [K; N] {
+        let mut keys = [Key::NULL.into(); N];
+        exhausted(reserve(
+            &mut keys,
+            self.tag,
+            self.cursor,
+            self.free,
+            self.last,
+        ));
+        let sequence = self.sequence(N as _);
+        let pairs = keys.iter().copied().zip(values);
+        self.inserts.lock().extend(pairs.zip(sequence..));
         keys
     }
 
+    /// Defers the insertion of `value` at a reserved `key` until the next resolve. Keys that can be rejected without
+    /// inspecting the slots (null, foreign or never reserved) fail immediately. Other failures are only known once the
+    /// insertion is resolved.
+    #[inline]
+    pub fn try_insert(&self, key: K, value: T) -> Result<(), InsertError<T, K>> {
+        let raw: Key = key.into();
+        let error = if raw == Key::NULL {
+            Error::Null
+        } else if !self.tag.accepts(raw) {
+            Error::Foreign
+        } else if raw.index >= self.last.load(Ordering::Relaxed) {
+            Error::Invalid
+        } else {
+            let sequence = self.sequence(1);
+            self.inserts.lock().push(((key, value), sequence));
+            return Ok(());
+        };
+        Err(InsertError { key, value, error })
+    }
+
+    /// Defers the replacement of the value of `key` with `value` until the next resolve. The replaced value is
+    /// reported as [`Event::Replaced`]. `value` is dropped if the key has no value by then. Deferred changes are
+    /// applied after the deferred inserts and before the deferred removes.
+    #[inline]
+    pub fn replace(&self, key: K, value: T) {
+        self.changes.lock().push((key, Change::Replace(value)));
+    }
+
+    /// Defers the removal of the values of `keys` until the next resolve.
     #[inline]
-    pub fn try_insert<P: IntoIterator<Item = Pair<T>>>(&self, pairs: P) {
-        self.inserts.lock().extend(pairs)
+    pub fn remove<I: IntoIterator<Item = K>>(&self, keys: I) {
+        let mut removes = self.removes.lock();
+        removes.extend(keys.into_iter().map(|key| (key, self.sequence(1))));
     }
 
+    /// Hands out `count` consecutive sequence numbers, which are only needed to settle [`Conflict::LastWins`].
     #[inline]
-    pub fn remove<K: IntoIterator<Item = Key>>(&self, keys: K) {
-        self.removes.lock().extend(keys);
+    fn sequence(&self, count: u64) -> u64 {
+        match self.conflict {
+            Conflict::RemoveWins => 0,
+            Conflict::LastWins => self.sequence.fetch_add(count, Ordering::Relaxed),
+        }
     }
 }
 
-impl<T> Pairs<'_, T> {
+impl<T, K: KeyType> Pairs<'_, T, K> {
     #[inline]
     pub fn len(&self) -> usize {
         self.pairs.len()
@@ -143,65 +535,130 @@ impl<T> Pairs<'_, T> {
     }
 
     #[inline]
-    pub fn has(&self, key: Key) -> bool {
-        index(key, self.slots).is_some()
+    pub fn has(&self, key: K) -> bool {
+        index(key.into(), self.slots, self.tag).is_some()
     }
 
     #[inline]
-    pub fn get(&self, key: Key) -> Option<&T> {
-        let index = index(key, self.slots)?;
+    pub fn get(&self, key: K) -> Option<&T> {
+        let index = index(key.into(), self.slots, self.tag)?;
         Some(&self.pairs[index].1)
     }
 
+    forks!();
+
     #[inline]
-    pub fn fork<'a, L: Item, R: Item>(
-        &'a mut self,
-        fork: impl Fn(Key, &'a mut T) -> (L, R) + Copy,
-    ) -> (
-        Fork<'a, Pair<T>, impl Fn(&'a mut Pair<T>) -> L>,
-        Fork<'a, Pair<T>, impl Fn(&'a mut Pair<T>) -> R>,
-    ) {
-        fork::fork(self.pairs, move |pair| fork(pair.0, &mut pair.1))
+    fn parts(&mut self) -> (&mut [Pair<T, K>], Lookup<'_>, Clock<'_>) {
+        (self.pairs, Lookup::Slots(self.slots, self.tag), self.clock)
     }
 
     #[inline]
-    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
-        let index = index(key, self.slots)?;
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        let index = index(key.into(), self.slots, self.tag)?;
+        self.clock.change(key.into());
         Some(&mut self.pairs[index].1)
     }
 
+    /// Gets mutable references to the values of `N` distinct keys at once. Returns `None` if any key is missing or if
+    /// a key is provided more than once.
+    #[inline]
+    pub fn get_many_mut<const N: usize>(&mut self, keys: [K; N]) -> Option<[&mut T; N]> {
+        self.try_get_many_mut(keys).ok()
+    }
+
+    /// Gets mutable references to the values of `N` distinct keys at once. Fails with the first key that is missing or
+    /// that is provided more than once.
+    #[inline]
+    pub fn try_get_many_mut<const N: usize>(
+        &mut self,
+        keys: [K; N],
+    ) -> Result<[&mut T; N], (K, Error)> {
+        let last = self.last.load(Ordering::Relaxed);
+        let values = many(keys, self.tag, last, self.slots, self.pairs)?;
+        for key in keys {
+            self.clock.change(key.into());
+        }
+        Ok(values)
+    }
+
+    /// Gets the entry of `key` for in-place manipulation. Keys removed through an entry are only recycled once the
+    /// scope is resolved.
+    #[inline]
+    pub fn entry(&mut self, key: K) -> Entry<'_, T, K> {
+        entry::entry(
+            key,
+            self.tag,
+            self.last.load(Ordering::Relaxed),
+            self.slots,
+            self.pairs,
+            self.pending,
+            self.clock,
+            self.observers.reborrow(),
+        )
+    }
+
     #[inline]
-    pub fn iter(&self) -> impl FullIterator<Item = (Key, &T)> {
+    pub fn iter(&self) -> impl FullIterator<Item = (K, &T)> {
         self.pairs.iter().map(|(key, value)| (*key, value))
     }
 
     #[inline]
-    pub fn iter_mut(&mut self) -> impl FullIterator<Item = (Key, &mut T)> {
-        self.pairs.iter_mut().map(|(key, value)| (*key, value))
+    pub fn iter_mut(&mut self) -> impl FullIterator<Item = (K, &mut T)> {
+        let clock = self.clock;
+        self.pairs.iter_mut().map(move |(key, value)| {
+            clock.change((*key).into());
+            (*key, value)
+        })
     }
 }
 
-impl<T: Send + Sync> Pairs<'_, T> {
+impl<T: Send + Sync, K: KeyType + Send + Sync> Pairs<'_, T, K> {
     #[inline]
-    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (Key, &T)> {
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (K, &T)> {
         self.pairs.par_iter().map(|(key, value)| (*key, value))
     }
 
     #[inline]
-    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = (Key, &mut T)> {
-        self.pairs.par_iter_mut().map(|(key, value)| (*key, value))
+    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = (K, &mut T)> {
+        let clock = self.clock;
+        self.pairs.par_iter_mut().map(move |(key, value)| {
+            clock.change((*key).into());
+            (*key, value)
+        })
+    }
+}
+
+impl<'b, T, K: KeyType> Defer<'_, T, K, Bound<'b>> {
+    /// Defers a call to `modify` with the value of `key` until the next resolve. It is skipped if the key has no value
+    /// by then. Deferred changes are applied after the deferred inserts and before the deferred removes. Within
+    /// [`Armoire::scope`], `modify` may borrow from outside of the scope.
+    #[inline]
+    pub fn modify<F: FnOnce(&mut T) + Send + 'b>(&self, key: K, modify: F) {
+        // SAFETY: `'b` is `'static` unless this defer comes from `Armoire::scope`, which applies or drops the deferred
+        // changes before returning, while `'b` is still alive.
+        let modify = unsafe {
+            transmute::<Box<dyn FnOnce(&mut T) + Send + 'b>, Box<dyn FnOnce(&mut T) + Send>>(
+                Box::new(modify),
+            )
+        };
+        self.changes.lock().push((key, Change::Modify(modify)));
     }
 }
 
-impl<T> Clone for Defer<'_, T> {
+impl<T, K, B> Clone for Defer<'_, T, K, B> {
     #[inline]
     fn clone(&self) -> Self {
         Self {
+            tag: self.tag,
+            conflict: self.conflict,
+            sequence: self.sequence,
             last: self.last,
             cursor: self.cursor,
             free: self.free,
             inserts: self.inserts,
+            changes: self.changes,
             removes: self.removes,
+            bound: PhantomData,
         }
     }
 }
@@ -209,14 +666,194 @@ impl<T> Clone for Defer<'_, T> {
 impl<T> Armoire<T> {
     #[inline]
     pub fn new() -> Self {
+        Self::with_key()
+    }
+}
+
+impl<T, K: KeyType> Armoire<T, K> {
+    #[inline]
+    pub fn with_key() -> Self {
         Self {
+            tag: Tag::new(),
+            order: Order::Arrival,
+            conflict: Conflict::RemoveWins,
+            sequence: AtomicU64::new(0),
+            tracked: false,
+            tick: 1,
+            ticks: Vec::new(),
+            log: None,
+            on_insert: Vec::new(),
+            on_remove: Vec::new(),
             last: AtomicU32::new(0),
             cursor: AtomicI64::new(0),
             slots: Vec::new(),
             free: Vec::new(),
+            pending: Vec::new(),
             pairs: Vec::new(),
-            inserts: Mutex::new(Vec::new()),
-            removes: Mutex::new(HashSet::new()),
+            inserts: Queue::new(),
+            changes: Queue::new(),
+            removes: Queue::new(),
+        }
+    }
+
+    #[inline]
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
+    /// Sets the order in which the next resolves apply deferred operations.
+    #[inline]
+    pub fn set_order(&mut self, order: Order) {
+        self.order = order;
+    }
+
+    #[inline]
+    pub fn conflict(&self) -> Conflict {
+        self.conflict
+    }
+
+    /// Sets how the next resolves settle deferred inserts and removes of the same key.
+    #[inline]
+    pub fn set_conflict(&mut self, conflict: Conflict) {
+        self.conflict = conflict;
+    }
+
+    #[inline]
+    pub fn tracking(&self) -> bool {
+        self.tracked
+    }
+
+    /// Enables or disables change tracking. While enabled, insertions and mutable accesses (including through
+    /// [`Pairs`], [`Fork::iter_mut`] and deferred operations) stamp the current tick on the key they touch, which
+    /// [`Armoire::iter_added_since`] and [`Armoire::iter_changed_since`] can then query. Enabling it stamps the
+    /// current tick on every present value.
+    pub fn set_tracking(&mut self, tracked: bool) {
+        self.tracked = tracked;
+        self.ticks.clear();
+        self.track();
+    }
+
+    /// Ends the current tick and returns it. Changes made from now on are stamped with a greater tick.
+    ///
+    /// # Panics
+    /// If `u32::MAX` ticks have elapsed.
+    #[inline]
+    pub fn tick(&mut self) -> u32 {
+        let tick = self.tick;
+        self.tick = tick.checked_add(1).expect("ticks are exhausted");
+        tick
+    }
+
+    /// Iterates over the pairs that have been inserted after `tick` ended. Yields every pair if change tracking is
+    /// disabled.
+    #[inline]
+    pub fn iter_added_since(&self, tick: u32) -> impl Iterator<Item = (K, &T)> {
+        let clock = Clock::new(&self.ticks, self.tick);
+        self.iter()
+            .filter(move |&(key, _)| clock.added_since(key.into(), tick))
+    }
+
+    /// Iterates over the pairs that have been inserted or mutably accessed after `tick` ended. Yields every pair if
+    /// change tracking is disabled.
+    #[inline]
+    pub fn iter_changed_since(&self, tick: u32) -> impl Iterator<Item = (K, &T)> {
+        let clock = Clock::new(&self.ticks, self.tick);
+        self.iter()
+            .filter(move |&(key, _)| clock.changed_since(key.into(), tick))
+    }
+
+    /// Registers a hook that is called with every key and value inserted through [`Self::insert_n`],
+    /// [`Self::try_insert_n`], [`Self::extend_pairs`] (and the methods built on them), [`Self::resolve`] or an
+    /// [`Entry`]. The hook
+    /// can defer further operations through the provided [`Defer`], which are applied on the next resolve.
+    pub fn on_insert<F: FnMut(K, &mut T, &Defer<T, K>) + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.on_insert.push(Box::new(hook));
+    }
+
+    /// Registers a hook that is called with every key and value removed through [`Self::remove_n`] (and the methods
+    /// built on it), [`Self::resolve`], [`Self::clear`], [`Self::drain`], [`Self::retain`], [`Self::truncate`],
+    /// [`Self::par_retain`], [`Self::par_drain_filter`] or an [`Entry`], right before the value is handed back or
+    /// dropped. The hook can defer further operations through the provided [`Defer`], which are applied on the next
+    /// resolve.
+    pub fn on_remove<F: FnMut(K, &mut T, &Defer<T, K>) + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.on_remove.push(Box::new(hook));
+    }
+
+    /// Calls the insert hooks with the values of `keys`.
+    fn inserted(&mut self, keys: impl IntoIterator<Item = K>) {
+        if self.on_insert.is_empty() {
+            return;
+        }
+        let defer = defer!(self);
+        for key in keys {
+            if let Some(index) = index(key.into(), &self.slots, self.tag) {
+                for hook in self.on_insert.iter_mut() {
+                    hook(key, &mut self.pairs[index].1, &defer);
+                }
+            }
+        }
+    }
+
+    /// Calls the remove hooks with a removed `value`.
+    fn removed(&mut self, key: K, value: &mut T) {
+        let defer = defer!(self);
+        for hook in self.on_remove.iter_mut() {
+            hook(key, value, &defer);
+        }
+    }
+
+    /// Calls the remove hooks with the pairs at the indices for which `removed` returns `true`, before they are
+    /// removed.
+    fn removing(&mut self, mut removed: impl FnMut(usize) -> bool) {
+        if self.on_remove.is_empty() {
+            return;
+        }
+        let defer = defer!(self);
+        for (index, (key, value)) in self.pairs.iter_mut().enumerate() {
+            if removed(index) {
+                for hook in self.on_remove.iter_mut() {
+                    hook(*key, value, &defer);
+                }
+            }
+        }
+    }
+
+    /// Removes `reader` such that events are no longer kept for it.
+    ///
+    /// # Panics
+    /// If `reader` was not subscribed to this armoire.
+    pub fn unsubscribe(&mut self, reader: Reader) {
+        self.log
+            .as_mut()
+            .expect("reader was not subscribed to this armoire")
+            .unsubscribe(reader);
+    }
+
+    /// Yields the events recorded since the last read of `reader`, oldest first.
+    ///
+    /// # Panics
+    /// If `reader` was not subscribed to this armoire.
+    #[inline]
+    pub fn read(&self, reader: &mut Reader) -> impl ExactSizeIterator<Item = &Event<T, K>> {
+        self.log
+            .as_ref()
+            .expect("reader was not subscribed to this armoire")
+            .read(reader)
+    }
+
+    /// Makes room for the ticks of every reserved key if change tracking is enabled.
+    #[inline]
+    fn track(&mut self) {
+        let last = *self.last.get_mut() as usize;
+        if self.tracked && self.ticks.len() < last {
+            let tick = self.tick;
+            self.ticks.resize_with(last, || Ticks::new(tick));
         }
     }
 
@@ -231,236 +868,856 @@ impl<T> Armoire<T> {
     }
 
     #[inline]
-    pub fn has(&self, key: Key) -> bool {
-        index(key, &self.slots).is_some()
+    pub fn has(&self, key: K) -> bool {
+        index(key.into(), &self.slots, self.tag).is_some()
     }
 
     #[inline]
-    pub fn get(&self, key: Key) -> Option<&T> {
-        let index = index(key, &self.slots)?;
+    pub fn get(&self, key: K) -> Option<&T> {
+        let index = index(key.into(), &self.slots, self.tag)?;
         Some(&self.pairs[index].1)
     }
 
     #[inline]
-    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
-        let index = index(key, &self.slots)?;
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        let index = index(key.into(), &self.slots, self.tag)?;
+        Clock::new(&self.ticks, self.tick).change(key.into());
         Some(&mut self.pairs[index].1)
     }
 
+    /// Gets mutable references to the values of `N` distinct keys at once. Returns `None` if any key is missing or if
+    /// a key is provided more than once.
+    #[inline]
+    pub fn get_many_mut<const N: usize>(&mut self, keys: [K; N]) -> Option<[&mut T; N]> {
+        self.try_get_many_mut(keys).ok()
+    }
+
+    /// Gets mutable references to the values of `N` distinct keys at once. Fails with the first key that is missing or
+    /// that is provided more than once.
     #[inline]
-    pub fn iter(&self) -> impl FullIterator<Item = (Key, &T)> {
+    pub fn try_get_many_mut<const N: usize>(
+        &mut self,
+        keys: [K; N],
+    ) -> Result<[&mut T; N], (K, Error)> {
+        let last = *self.last.get_mut();
+        let values = many(keys, self.tag, last, &self.slots, &mut self.pairs)?;
+        let clock = Clock::new(&self.ticks, self.tick);
+        for key in keys {
+            clock.change(key.into());
+        }
+        Ok(values)
+    }
+
+    /// Gets the entry of `key` for in-place manipulation. A key that was reserved but not inserted yields a
+    /// [`Entry::Vacant`] and a key that is outdated or unknown to this armoire yields a [`Entry::Stale`].
+    #[inline]
+    pub fn entry(&mut self, key: K) -> Entry<'_, T, K> {
+        self.track();
+        let last = *self.last.get_mut();
+        let observers = Observers {
+            on_insert: &mut self.on_insert,
+            on_remove: &mut self.on_remove,
+            log: self.log.as_mut(),
+            defer: defer!(self),
+        };
+        entry::entry(
+            key,
+            self.tag,
+            last,
+            &mut self.slots,
+            &mut self.pairs,
+            &mut self.pending,
+            Clock::new(&self.ticks, self.tick),
+            observers,
+        )
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl FullIterator<Item = (K, &T)> {
         self.pairs.iter().map(|(key, value)| (*key, value))
     }
 
     #[inline]
-    pub fn iter_mut(&mut self) -> impl FullIterator<Item = (Key, &mut T)> {
-        self.pairs.iter_mut().map(|(key, value)| (*key, value))
+    pub fn iter_mut(&mut self) -> impl FullIterator<Item = (K, &mut T)> {
+        let clock = Clock::new(&self.ticks, self.tick);
+        self.pairs.iter_mut().map(move |(key, value)| {
+            clock.change((*key).into());
+            (*key, value)
+        })
     }
 
+    /// # Panics
+    /// If key indices are exhausted.
     #[inline]
-    pub fn insert(&mut self, value: T) -> Key {
+    pub fn insert(&mut self, value: T) -> K {
         let [key] = self.insert_n([value]);
         key
     }
 
-    pub fn insert_n<const N: usize>(&mut self, values: [T; N]) -> [Key; N] {
-        let keys = self.reserve_n_mut();
+    /// # Panics
+    /// If key indices are exhausted.
+    pub fn insert_n<const N: usize>(&mut self, values: [T; N]) -> [K; N] {
+        let keys = exhausted(self.reserve_n_mut());
         ensure(&mut self.last, &mut self.slots);
+        self.track();
+        let clock = Clock::new(&self.ticks, self.tick);
         for (key, value) in keys.iter().copied().zip(values) {
-            self.slots[key.index as usize].initialize(key.generation, self.pairs.len() as _);
+            let raw: Key = key.into();
+            self.slots[raw.index as usize].initialize(raw.generation, self.pairs.len() as _);
             self.pairs.push((key, value));
+            clock.add(raw);
+            if let Some(log) = &mut self.log {
+                log.push(Event::Inserted(key));
+            }
         }
+        self.inserted(keys);
         keys
     }
 
+    /// Inserts `value` at a reserved `key`.
     #[inline]
-    pub fn try_insert(&mut self, key: Key, value: T) -> Result<(), T> {
+    pub fn try_insert(&mut self, key: K, value: T) -> Result<(), InsertError<T, K>> {
         let [result] = self.try_insert_n([(key, value)]);
         result
     }
 
     #[inline]
-    pub fn try_insert_n<const N: usize>(&mut self, pairs: [Pair<T>; N]) -> [Result<(), T>; N] {
-        insert(pairs, &mut self.pairs, &mut self.last, &mut self.slots)
+    pub fn try_insert_n<const N: usize>(
+        &mut self,
+        pairs: [Pair<T, K>; N],
+    ) -> [Result<(), InsertError<T, K>>; N] {
+        ensure(&mut self.last, &mut self.slots);
+        self.track();
+        let last = *self.last.get_mut();
+        let clock = Clock::new(&self.ticks, self.tick);
+        let keys = pairs.each_ref().map(|&(key, _)| key);
+        let results = pairs.map(|(key, value)| {
+            let result = insert(
+                key,
+                value,
+                self.tag,
+                last,
+                &mut self.pairs,
+                &mut self.slots,
+                clock,
+            );
+            if let (Ok(()), Some(log)) = (&result, &mut self.log) {
+                log.push(Event::Inserted(key));
+            }
+            result
+        });
+        let inserted = keys.iter().zip(&results);
+        self.inserted(
+            inserted
+                .filter(|(_, result)| result.is_ok())
+                .map(|(&key, _)| key),
+        );
+        results
+    }
+
+    /// Inserts all `values` and returns their keys in order. See [`Extend`] to discard the keys.
+    ///
+    /// # Panics
+    /// If key indices are exhausted.
+    pub fn insert_iter<I: IntoIterator<Item = T>>(&mut self, values: I) -> Vec<K> {
+        let values = values.into_iter();
+        self.pairs.reserve(values.size_hint().0);
+        values.map(|value| self.insert(value)).collect()
+    }
+
+    /// Inserts all `pairs` at their reserved keys and returns the insertions that failed.
+    pub fn extend_pairs<I: IntoIterator<Item = Pair<T, K>>>(
+        &mut self,
+        pairs: I,
+    ) -> Vec<InsertError<T, K>> {
+        ensure(&mut self.last, &mut self.slots);
+        self.track();
+        let last = *self.last.get_mut();
+        let clock = Clock::new(&self.ticks, self.tick);
+        let mut inserted = Vec::new();
+        let errors = pairs
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let result = insert(
+                    key,
+                    value,
+                    self.tag,
+                    last,
+                    &mut self.pairs,
+                    &mut self.slots,
+                    clock,
+                );
+                if result.is_ok() {
+                    inserted.push(key);
+                    if let Some(log) = &mut self.log {
+                        log.push(Event::Inserted(key));
+                    }
+                }
+                result.err()
+            })
+            .collect();
+        self.inserted(inserted);
+        errors
     }
 
     #[inline]
-    pub fn remove(&mut self, key: Key) -> Option<T> {
+    pub fn remove(&mut self, key: K) -> Result<T, Error> {
         let [value] = self.remove_n([key]);
         value
     }
 
     #[inline]
-    pub fn remove_n<const N: usize>(&mut self, keys: [Key; N]) -> [Option<T>; N] {
-        remove(
-            keys,
-            &mut self.pairs,
-            &mut self.slots,
-            &mut self.free,
-            &mut self.cursor,
-        )
+    pub fn remove_n<const N: usize>(&mut self, keys: [K; N]) -> [Result<T, Error>; N] {
+        let last = *self.last.get_mut();
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        let mut values = keys.map(|key| {
+            let result = remove(
+                key.into(),
+                self.tag,
+                last,
+                &mut self.pairs,
+                &mut self.slots,
+                &mut self.free,
+            );
+            if let (Ok(value), Some(log)) = (&result, &mut self.log) {
+                log.removed(key, value);
+            }
+            result
+        });
+        *cursor = self.free.len() as _;
+        for (key, value) in keys.into_iter().zip(values.iter_mut()) {
+            if let Ok(value) = value {
+                self.removed(key, value);
+            }
+        }
+        values
     }
 
+    /// Reserves keys that can later be inserted with [`Self::try_insert`] or [`Defer::try_insert`]. Fails with
+    /// [`Error::Exhausted`] if there are not enough key indices left, in which case the keys that could be taken from
+    /// the free list are put back. If another reservation ran concurrently, they cannot be put back and remain reserved
+    /// until [`Self::clear`]; use [`Self::reserve_mut`] to never lose keys.
     #[inline]
-    pub fn reserve(&self, keys: &mut [Key]) {
-        reserve(keys, &self.cursor, &self.free, &self.last)
+    pub fn reserve(&self, keys: &mut [K]) -> Result<(), Error> {
+        reserve(keys, self.tag, &self.cursor, &self.free, &self.last)
     }
 
+    /// Same as [`Self::reserve`], but nothing is reserved on failure.
     #[inline]
-    pub fn reserve_mut(&mut self, keys: &mut [Key]) {
-        reserve_mut(keys, &mut self.cursor, &self.free, &mut self.last)
+    pub fn reserve_mut(&mut self, keys: &mut [K]) -> Result<(), Error> {
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        *cursor = self.free.len() as _;
+        reserve_mut(keys, self.tag, &mut self.cursor, &self.free, &mut self.last)
     }
 
     #[inline]
-    pub fn reserve_n<const N: usize>(&self) -> [Key; N] {
-        let mut keys = [Key::NULL; N];
-        self.reserve(&mut keys);
-        keys
+    pub fn reserve_n<const N: usize>(&self) -> Result<[K; N], Error> {
+        let mut keys = [Key::NULL.into(); N];
+        self.reserve(&mut keys)?;
+        Ok(keys)
     }
 
     #[inline]
-    pub fn reserve_n_mut<const N: usize>(&mut self) -> [Key; N] {
-        let mut keys = [Key::NULL; N];
-        self.reserve_mut(&mut keys);
-        keys
+    pub fn reserve_n_mut<const N: usize>(&mut self) -> Result<[K; N], Error> {
+        let mut keys = [Key::NULL.into(); N];
+        self.reserve_mut(&mut keys)?;
+        Ok(keys)
+    }
+
+    /// Releases reserved keys that have no value such that they can be reserved again. Other keys are ignored. Deferred
+    /// inserts at a released key are rejected as [`Error::Stale`].
+    pub fn release(&mut self, keys: impl IntoIterator<Item = K>) {
+        ensure(&mut self.last, &mut self.slots);
+        let last = *self.last.get_mut();
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        for key in keys {
+            if discard(key.into(), self.tag, last, &mut self.slots, &mut self.free) {
+                if let Some(log) = &mut self.log {
+                    log.push(Event::Released(key));
+                }
+            }
+        }
+        *cursor = self.free.len() as _;
+    }
+
+    /// Removes all values and invalidates all outstanding keys, including reserved ones. Deferred operations that have
+    /// not been resolved yet are discarded, except for those deferred by the remove hooks.
+    pub fn clear(&mut self) {
+        self.invalidate();
+        self.removing(|_| true);
+        match &mut self.log {
+            Some(log) => {
+                for (key, value) in self.pairs.drain(..) {
+                    log.push(Event::Removed(key, value));
+                }
+            }
+            None => self.pairs.clear(),
+        }
+    }
+
+    /// Same as [`Self::clear`], but yields the removed pairs. All keys are invalidated even if the iterator is not
+    /// consumed.
+    pub fn drain(&mut self) -> impl FullIterator<Item = Pair<T, K>> + '_ {
+        self.invalidate();
+        self.removing(|_| true);
+        if let Some(log) = &mut self.log {
+            for (key, value) in self.pairs.iter() {
+                log.removed(*key, value);
+            }
+        }
+        self.pairs.drain(..)
+    }
+
+    /// Keeps only the pairs for which `keep` returns `true` and invalidates the keys of the others. The relative order
+    /// of the kept pairs is preserved.
+    pub fn retain<F: FnMut(K, &mut T) -> bool>(&mut self, mut keep: F) {
+        if !self.on_remove.is_empty() {
+            // The hooks need the whole armoire, so every pair is decided before any is removed.
+            let keep = self
+                .pairs
+                .iter_mut()
+                .map(|(key, value)| keep(*key, value))
+                .collect::<Vec<_>>();
+            return self.retain_at(&keep);
+        }
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        retain(
+            &mut self.pairs,
+            &mut self.slots,
+            &mut self.free,
+            |_, (key, value)| keep(*key, value),
+            |key, value| {
+                if let Some(log) = &mut self.log {
+                    log.push(Event::Removed(key, value));
+                }
+            },
+        );
+        *cursor = self.free.len() as _;
+    }
+
+    /// Keeps only the pairs at the indices for which `keep` is `true`, after calling the remove hooks with the others.
+    fn retain_at(&mut self, keep: &[bool]) {
+        self.removing(|index| !keep[index]);
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        retain(
+            &mut self.pairs,
+            &mut self.slots,
+            &mut self.free,
+            |index, _| keep[index],
+            |key, value| {
+                if let Some(log) = &mut self.log {
+                    log.push(Event::Removed(key, value));
+                }
+            },
+        );
+        *cursor = self.free.len() as _;
     }
 
-    /// Releases reserved keys. Use only with keys that are valid (i.e. acquired through [`Self::reserve`]) and that have
-    /// not been inserted, otherwise there may be key collisions on later [`Self::reserve`] or [`Self::insert`] calls.
-    pub fn release(&mut self, keys: impl IntoIterator<Item = Key>) {
+    /// Removes the pairs beyond the first `len` ones in iteration order and invalidates their keys.
+    pub fn truncate(&mut self, len: usize) {
+        self.removing(|index| index >= len);
         let cursor = self.cursor.get_mut();
-        self.free.truncate((*cursor).max(0) as usize);
-        self.free
-            .extend(keys.into_iter().filter_map(Key::increment));
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        truncate(
+            &mut self.pairs,
+            &mut self.slots,
+            &mut self.free,
+            len,
+            |key, value| {
+                if let Some(log) = &mut self.log {
+                    log.push(Event::Removed(key, value));
+                }
+            },
+        );
         *cursor = self.free.len() as _;
     }
 
+    /// Defers operations through `scope` and resolves them before returning. Unlike with [`Self::defer`], closures
+    /// deferred with [`Defer::modify`] may borrow from outside of `scope`. If `scope` or the resolve panics, the
+    /// deferred changes that have not been applied are dropped.
     #[inline]
-    pub fn scope<U, S: FnOnce(Pairs<T>, Defer<T>) -> U>(&mut self, scope: S) -> U {
-        let (pairs, defer) = self.defer();
-        let value = scope(pairs, defer);
-        self.resolve();
-        value
+    pub fn scope<'b, U, S: FnOnce(Pairs<T, K>, Defer<T, K, Bound<'b>>) -> U>(
+        &'b mut self,
+        scope: S,
+    ) -> U {
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let (pairs, defer) = self.split();
+            let value = scope(pairs, defer);
+            self.resolve();
+            value
+        }));
+        result.unwrap_or_else(|payload| {
+            // The changes may borrow from `'b`, such that they must not outlive this call.
+            self.changes.clear();
+            resume_unwind(payload)
+        })
     }
 
+    forks!();
+
     #[inline]
-    pub fn fork<'a, L: Item, R: Item>(
-        &'a mut self,
-        fork: impl Fn(Key, &'a mut T) -> (L, R) + Copy,
-    ) -> (
-        Fork<'a, Pair<T>, impl Fn(&'a mut Pair<T>) -> L>,
-        Fork<'a, Pair<T>, impl Fn(&'a mut Pair<T>) -> R>,
-    ) {
-        fork::fork(&mut self.pairs, move |pair| fork(pair.0, &mut pair.1))
+    fn parts(&mut self) -> (&mut [Pair<T, K>], Lookup<'_>, Clock<'_>) {
+        self.track();
+        let lookup = Lookup::Slots(&self.slots, self.tag);
+        (&mut self.pairs, lookup, Clock::new(&self.ticks, self.tick))
     }
 
     #[inline]
-    pub fn defer(&mut self) -> (Pairs<T>, Defer<T>) {
+    pub fn defer(&mut self) -> (Pairs<'_, T, K>, Defer<'_, T, K>) {
+        self.split()
+    }
+
+    #[inline]
+    fn split<B>(&mut self) -> (Pairs<'_, T, K>, Defer<'_, T, K, B>) {
+        self.track();
         let pairs = Pairs {
+            tag: self.tag,
+            last: &self.last,
             slots: &mut self.slots,
+            pending: &mut self.pending,
             pairs: &mut self.pairs,
+            clock: Clock::new(&self.ticks, self.tick),
+            observers: Observers {
+                on_insert: &mut self.on_insert,
+                on_remove: &mut self.on_remove,
+                log: self.log.as_mut(),
+                defer: defer!(self),
+            },
         };
-        let defer = Defer {
-            cursor: &self.cursor,
-            free: &self.free,
-            last: &self.last,
-            inserts: &self.inserts,
-            removes: &self.removes,
-        };
-        (pairs, defer)
+        (pairs, defer!(self))
     }
 
+    /// Bumps the generation of every slot and rebuilds the free list from them.
+    fn invalidate(&mut self) {
+        ensure(&mut self.last, &mut self.slots);
+        self.inserts.clear();
+        self.changes.clear();
+        self.removes.clear();
+        self.pending.clear();
+        self.free.clear();
+        for (index, slot) in self.slots.iter_mut().enumerate().rev() {
+            let key = Key::new(slot.generation, index as _, self.tag);
+            slot.generation = slot.generation.saturating_add(1);
+            slot.index = u32::MAX;
+            self.free.extend(key.increment());
+        }
+        *self.cursor.get_mut() = self.free.len() as _;
+    }
+
+    /// Applies the operations deferred through [`Defer`] and makes the reserved keys available again.
+    #[inline]
     pub fn resolve(&mut self) {
-        for pair in self.inserts.get_mut().drain(..) {
-            // TODO: Batch?
-            let _ = insert([pair], &mut self.pairs, &mut self.last, &mut self.slots);
-        }
-        for key in self.removes.get_mut().drain() {
-            // TODO: Batch?
-            let _ = remove(
-                [key],
+        self.resolve_with(|_| {});
+    }
+
+    /// Same as [`Armoire::resolve`], but reports every inserted, rejected, replaced, removed and released key to
+    /// `report`.
+    pub fn resolve_with<F: FnMut(Event<T, K>)>(&mut self, report: F) {
+        if self.on_insert.is_empty() && self.on_remove.is_empty() {
+            return self.apply(report);
+        }
+
+        // Hooks run once the deferred operations are applied, such that they can defer more of them.
+        let mut events = Vec::new();
+        self.apply(|event| events.push(event));
+        for position in 0..events.len() {
+            let (event, rest) = events[position..]
+                .split_first_mut()
+                .expect("index is in bounds");
+            match event {
+                &mut Event::Inserted(key) if !self.on_insert.is_empty() => {
+                    // A key that has been removed again in the same resolve is observed through its removed value.
+                    let defer = defer!(self);
+                    let value = match index(key.into(), &self.slots, self.tag) {
+                        Some(index) => Some(&mut self.pairs[index].1),
+                        None => rest.iter_mut().find_map(|event| match event {
+                            Event::Removed(removed, value) if *removed == key => Some(value),
+                            _ => None,
+                        }),
+                    };
+                    if let Some(value) = value {
+                        for hook in self.on_insert.iter_mut() {
+                            hook(key, value, &defer);
+                        }
+                    }
+                }
+                Event::Removed(key, value) => self.removed(*key, value),
+                _ => {}
+            }
+        }
+        events.into_iter().for_each(report);
+    }
+
+    /// Applies the deferred operations, reporting their events to `report` and to the log.
+    fn apply<F: FnMut(Event<T, K>)>(&mut self, mut report: F) {
+        ensure(&mut self.last, &mut self.slots);
+        self.track();
+        let log = &mut self.log;
+        let mut report = |event: Event<T, K>| {
+            if let Some(log) = log {
+                log.record(&event);
+            }
+            report(event);
+        };
+        let last = *self.last.get_mut();
+        let clock = Clock::new(&self.ticks, self.tick);
+        let (tag, order, conflict) = (self.tag, self.order, self.conflict);
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+
+        let mut holes = Vec::new();
+        let removes = sequenced(&mut self.removes, order, conflict, |&(key, sequence)| {
+            (key, sequence)
+        });
+        let removes = match conflict {
+            Conflict::RemoveWins => Either::Left(removes),
+            Conflict::LastWins => {
+                // Removes that are followed by an insert of the same key make room for it before the inserts.
+                let mut latest = HashMap::new();
+                for &((key, _), sequence) in self.inserts.iter() {
+                    let latest = latest.entry(key).or_insert(sequence);
+                    *latest = sequence.max(*latest);
+                }
+                let (early, late) = removes.partition::<Vec<_>, _>(|(key, sequence)| {
+                    latest.get(key).is_some_and(|latest| sequence < latest)
+                });
+                for (key, _) in early {
+                    let key: Key = key.into();
+                    if find(key, &self.slots, last, tag).is_ok() {
+                        let index = self.slots[key.index as usize].vacate(key.generation);
+                        holes.extend(index.map(|index| index as usize));
+                    }
+                }
+                compact(&mut self.pairs, &mut self.slots, &holes, |key, value| {
+                    report(Event::Removed(key, value))
+                });
+                holes.clear();
+                Either::Right(late.into_iter())
+            }
+        };
+
+        self.pairs.reserve(self.inserts.len());
+        let inserts = sequenced(
+            &mut self.inserts,
+            order,
+            conflict,
+            |&((key, _), sequence)| (key, sequence),
+        );
+        for ((key, value), _) in inserts {
+            match insert(
+                key,
+                value,
+                tag,
+                last,
                 &mut self.pairs,
                 &mut self.slots,
-                &mut self.free,
-                &mut self.cursor,
-            );
+                clock,
+            ) {
+                Ok(()) => report(Event::Inserted(key)),
+                Err(InsertError {
+                    key,
+                    value,
+                    error: Error::Occupied,
+                }) if conflict == Conflict::LastWins => {
+                    let raw: Key = key.into();
+                    let index = self.slots[raw.index as usize].index as usize;
+                    let value = replace(&mut self.pairs[index].1, value);
+                    clock.change(raw);
+                    report(Event::Replaced(key, value));
+                }
+                Err(error) => report(Event::Rejected(error)),
+            }
         }
+
+        let changes = match order {
+            Order::Arrival => Either::Left(self.changes.drain()),
+            Order::Sorted => Either::Right(self.changes.sorted_by_key(|&(key, _)| key)),
+        };
+        for (key, change) in changes {
+            let Ok(index) = find(key.into(), &self.slots, last, tag) else {
+                continue;
+            };
+            clock.change(key.into());
+            let value = &mut self.pairs[index].1;
+            match change {
+                Change::Modify(modify) => modify(value),
+                Change::Replace(new) => report(Event::Replaced(key, replace(value, new))),
+            }
+        }
+
+        for (key, _) in removes {
+            // Duplicate keys fail to `find` their already released slot.
+            let key: Key = key.into();
+            match find(key, &self.slots, last, tag) {
+                Ok(_) => {
+                    if let Some(index) = self.slots[key.index as usize].release(key.generation) {
+                        holes.push(index as usize);
+                        self.free.extend(key.increment());
+                    }
+                }
+                Err(Error::Vacant) => {
+                    if discard(key, tag, last, &mut self.slots, &mut self.free) {
+                        report(Event::Released(key.into()));
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        compact(&mut self.pairs, &mut self.slots, &holes, |key, value| {
+            report(Event::Removed(key, value))
+        });
+        *cursor = self.free.len() as _;
+        *self.sequence.get_mut() = 0;
     }
 }
 
-impl<T: Send + Sync> Armoire<T> {
+impl<T: Send + Sync, K: KeyType + Send + Sync> Armoire<T, K> {
     #[inline]
-    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (Key, &T)> {
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (K, &T)> {
         self.pairs.par_iter().map(|(key, value)| (*key, value))
     }
 
     #[inline]
-    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = (Key, &mut T)> {
-        self.pairs.par_iter_mut().map(|(key, value)| (*key, value))
+    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = (K, &mut T)> {
+        let clock = Clock::new(&self.ticks, self.tick);
+        self.pairs.par_iter_mut().map(move |(key, value)| {
+            clock.change((*key).into());
+            (*key, value)
+        })
     }
 }
 
-impl<T> Default for Armoire<T> {
+impl<T: Send, K: KeyType + Send + Sync> Armoire<T, K> {
+    /// Same as [`Self::retain`], but `keep` is evaluated in parallel. The pairs are then compacted sequentially.
+    pub fn par_retain<F: Fn(K, &mut T) -> bool + Sync>(&mut self, keep: F) {
+        let keep = self
+            .pairs
+            .par_iter_mut()
+            .map(|(key, value)| keep(*key, value))
+            .collect::<Vec<_>>();
+        self.retain_at(&keep);
+    }
+
+    /// Removes the pairs for which `filter` returns `true` and yields them. `filter` is evaluated in parallel and the
+    /// keys of the removed pairs are invalidated before this returns, even if the iterator is not consumed.
+    pub fn par_drain_filter<F: Fn(K, &mut T) -> bool + Sync>(
+        &mut self,
+        filter: F,
+    ) -> impl IndexedParallelIterator<Item = Pair<T, K>> {
+        let drain = self
+            .pairs
+            .par_iter_mut()
+            .map(|(key, value)| filter(*key, value))
+            .collect::<Vec<_>>();
+        self.removing(|index| drain[index]);
+        let mut drained = Vec::with_capacity(drain.iter().filter(|&&drain| drain).count());
+        let cursor = self.cursor.get_mut();
+        recycle(&mut self.free, &mut self.pending, *cursor);
+        retain(
+            &mut self.pairs,
+            &mut self.slots,
+            &mut self.free,
+            |index, _| !drain[index],
+            |key, value| {
+                if let Some(log) = &mut self.log {
+                    log.removed(key, &value);
+                }
+                drained.push((key, value));
+            },
+        );
+        *cursor = self.free.len() as _;
+        drained.into_par_iter()
+    }
+}
+
+impl<T: Clone, K: KeyType> Armoire<T, K> {
+    /// Adds a reader of the insertions and removals of this armoire, starting from now. Events are kept until every
+    /// reader has read them, so readers that are no longer read must be [unsubscribed](Self::unsubscribe). Values
+    /// that are removed and also handed back to the caller (such as by [`Self::remove`] or [`Self::drain`]) are
+    /// cloned into the log.
+    pub fn subscribe(&mut self) -> Reader {
+        self.log
+            .get_or_insert_with(|| Log::new(T::clone))
+            .subscribe()
+    }
+}
+
+impl<T, K: KeyType> Default for Armoire<T, K> {
     fn default() -> Self {
-        Self::new()
+        Self::with_key()
     }
 }
 
+impl<T, K: KeyType> Extend<T> for Armoire<T, K> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, values: I) {
+        let values = values.into_iter();
+        self.pairs.reserve(values.size_hint().0);
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
+impl<T, K: KeyType> FromIterator<T> for Armoire<T, K> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        let mut armoire = Self::with_key();
+        armoire.extend(values);
+        armoire
+    }
+}
+
+/// Projects the value of a pair into an item that marks the value as changed when it is written, if any.
 #[inline]
-fn index(key: Key, slots: &[Slot]) -> Option<usize> {
+fn filtered<'a, T, K: KeyType, I>(
+    clock: Clock<'a>,
+    project: impl Fn(K, *mut T) -> Option<I> + Copy + 'a,
+) -> impl Fn(*mut Pair<T, K>) -> Option<Tracked<'a, I>> + Copy + 'a {
+    move |pair| {
+        let key = unsafe { (*pair).0 };
+        project(key, unsafe { &raw mut (*pair).1 }).map(|item| clock.track(key.into(), item))
+    }
+}
+
+#[inline]
+fn index(key: Key, slots: &[Slot], tag: Tag) -> Option<usize> {
+    if !tag.accepts(key) {
+        return None;
+    }
     let slot = slots.get(key.index as usize)?;
-    if slot.generation == key.generation {
+    if slot.generation == key.generation && slot.index < u32::MAX {
         Some(slot.index as usize)
     } else {
         None
     }
 }
 
+/// Same as [`index`], but explains why the `key` could not be resolved.
+fn find(key: Key, slots: &[Slot], last: u32, tag: Tag) -> Result<usize, Error> {
+    if key == Key::NULL {
+        return Err(Error::Null);
+    } else if !tag.accepts(key) {
+        return Err(Error::Foreign);
+    }
+
+    match slots.get(key.index as usize) {
+        Some(slot) if slot.generation == key.generation && slot.index < u32::MAX => {
+            Ok(slot.index as usize)
+        }
+        Some(slot) if slot.generation == key.generation => Err(Error::Vacant),
+        Some(slot) if slot.generation > key.generation => Err(Error::Stale),
+        None if key.index < last && key.generation == 0 => Err(Error::Vacant),
+        _ => Err(Error::Invalid),
+    }
+}
+
+fn many<'a, T, K: KeyType, const N: usize>(
+    keys: [K; N],
+    tag: Tag,
+    last: u32,
+    slots: &[Slot],
+    pairs: &'a mut [Pair<T, K>],
+) -> Result<[&'a mut T; N], (K, Error)> {
+    let mut indices = [0; N];
+    for (index, key) in indices.iter_mut().zip(keys) {
+        *index = find(key.into(), slots, last, tag).map_err(|error| (key, error))?;
+    }
+
+    match pairs.get_disjoint_mut(indices) {
+        Ok(pairs) => Ok(pairs.map(|pair| &mut pair.1)),
+        Err(_) => {
+            let duplicate = (1..N)
+                .find(|&i| indices[..i].contains(&indices[i]))
+                .unwrap_or(0);
+            Err((keys[duplicate], Error::Duplicate))
+        }
+    }
+}
+
 #[inline]
 fn ensure(last: &mut AtomicU32, slots: &mut Vec<Slot>) {
     let last = *last.get_mut();
     slots.resize(last as _, Slot::ZERO);
 }
 
-fn reserve(keys: &mut [Key], cursor: &AtomicI64, free: &[Key], last: &AtomicU32) {
+#[inline]
+fn copy<K: KeyType>(target: &mut [K], source: &[Key]) {
+    for (target, source) in target.iter_mut().zip(source) {
+        *target = (*source).into();
+    }
+}
+
+fn reserve<K: KeyType>(
+    keys: &mut [K],
+    tag: Tag,
+    cursor: &AtomicI64,
+    free: &[Key],
+    last: &AtomicU32,
+) -> Result<(), Error> {
     if keys.is_empty() {
-        return;
+        return Ok(());
     }
 
-    let cursor = cursor.fetch_sub(keys.len() as _, Ordering::Relaxed);
-    let keys = if cursor > 0 {
-        let end = cursor as usize;
+    let count = keys.len() as i64;
+    let start = cursor.fetch_sub(count, Ordering::Relaxed);
+    let keys = if start > 0 {
+        let end = start as usize;
         if end >= keys.len() {
-            keys.copy_from_slice(&free[end - keys.len()..end]);
-            return;
+            copy(keys, &free[end - keys.len()..end]);
+            return Ok(());
         } else {
-            keys[..end].copy_from_slice(&free[..end]);
+            copy(&mut keys[..end], &free[..end]);
             &mut keys[end..]
         }
     } else {
         keys
     };
 
-    let last = last.fetch_add(keys.len() as _, Ordering::Relaxed);
-    assert!(last <= u32::MAX - keys.len() as u32);
+    let Some(last) = u32::try_from(keys.len()).ok().and_then(|count| {
+        last.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            last.checked_add(count)
+        })
+        .ok()
+    }) else {
+        // Puts the keys taken from the free list back, unless a concurrent reservation took keys below them.
+        let _ = cursor.compare_exchange(start - count, start, Ordering::Relaxed, Ordering::Relaxed);
+        return Err(Error::Exhausted);
+    };
     for (i, key) in keys.iter_mut().enumerate() {
-        *key = Key::new(0, last.saturating_add(i as _));
+        *key = Key::new(0, last + i as u32, tag).into();
     }
+    Ok(())
 }
 
-fn reserve_mut(keys: &mut [Key], cursor: &mut AtomicI64, free: &[Key], last: &mut AtomicU32) {
+fn reserve_mut<K: KeyType>(
+    keys: &mut [K],
+    tag: Tag,
+    cursor: &mut AtomicI64,
+    free: &[Key],
+    last: &mut AtomicU32,
+) -> Result<(), Error> {
     if keys.is_empty() {
-        return;
+        return Ok(());
     }
 
+    let available = (*cursor.get_mut()).max(0) as usize;
+    u32::try_from(keys.len().saturating_sub(available))
+        .ok()
+        .and_then(|count| last.get_mut().checked_add(count))
+        .ok_or(Error::Exhausted)?;
+
     let cursor = sub(cursor.get_mut(), keys.len() as _);
     let keys = if cursor > 0 {
         let end = cursor as usize;
         if end >= keys.len() {
-            keys.copy_from_slice(&free[end - keys.len()..end]);
-            return;
+            copy(keys, &free[end - keys.len()..end]);
+            return Ok(());
         } else {
-            keys[..end].copy_from_slice(&free[..end]);
+            copy(&mut keys[..end], &free[..end]);
             &mut keys[end..]
         }
     } else {
@@ -468,60 +1725,185 @@ fn reserve_mut(keys: &mut [Key], cursor: &mut AtomicI64, free: &[Key], last: &mu
     };
 
     let last = add(last.get_mut(), keys.len() as _);
-    assert!(last <= u32::MAX - keys.len() as u32);
     for (i, key) in keys.iter_mut().enumerate() {
-        *key = Key::new(0, last.wrapping_add(i as _));
+        *key = Key::new(0, last + i as u32, tag).into();
     }
+    Ok(())
 }
 
-fn insert<T, const N: usize>(
-    inserts: [Pair<T>; N],
-    pairs: &mut Vec<Pair<T>>,
-    last: &mut AtomicU32,
-    slots: &mut Vec<Slot>,
-) -> [Result<(), T>; N] {
-    ensure(last, slots);
-    inserts.map(|(key, value)| {
-        if let Some(slot) = slots.get_mut(key.index as usize) {
-            if slot.initialize(key.generation, pairs.len() as _) {
-                pairs.push((key, value));
-                return Ok(());
-            }
+fn insert<T, K: KeyType>(
+    key: K,
+    value: T,
+    tag: Tag,
+    last: u32,
+    pairs: &mut Vec<Pair<T, K>>,
+    slots: &mut [Slot],
+    clock: Clock,
+) -> Result<(), InsertError<T, K>> {
+    let raw: Key = key.into();
+    let error = match find(raw, slots, last, tag) {
+        Ok(_) => Error::Occupied,
+        Err(Error::Vacant) => {
+            slots[raw.index as usize].initialize(raw.generation, pairs.len() as _);
+            pairs.push((key, value));
+            clock.add(raw);
+            return Ok(());
         }
-        Err(value)
-    })
+        Err(error) => error,
+    };
+    Err(InsertError { key, value, error })
 }
 
-fn remove<T, const N: usize>(
-    removes: [Key; N],
-    pairs: &mut Vec<Pair<T>>,
+fn remove<T, K: KeyType>(
+    key: Key,
+    tag: Tag,
+    last: u32,
+    pairs: &mut Vec<Pair<T, K>>,
     slots: &mut [Slot],
     free: &mut Vec<Key>,
-    cursor: &mut AtomicI64,
-) -> [Option<T>; N] {
-    let cursor = cursor.get_mut();
-    free.truncate((*cursor).max(0) as usize);
-    let values = removes.map(|key| {
-        let slot = slots.get_mut(key.index as usize)?;
-        if let Some(index) = slot.release(key.generation) {
-            let pair = pairs.swap_remove(index as _);
-            debug_assert_eq!(pair.0, key);
-
-            if let Some((key, _)) = pairs.get(index as usize) {
-                slots[key.index as usize].update(index);
+) -> Result<T, Error> {
+    find(key, slots, last, tag)?;
+    take(key, pairs, slots, free).ok_or(Error::Stale)
+}
+
+/// Removes the pair of `key` from `pairs` and pushes the recycled key to `free`.
+fn take<T, K: KeyType>(
+    key: Key,
+    pairs: &mut Vec<Pair<T, K>>,
+    slots: &mut [Slot],
+    free: &mut Vec<Key>,
+) -> Option<T> {
+    let slot = slots.get_mut(key.index as usize)?;
+    let index = slot.release(key.generation)?;
+    let pair = pairs.swap_remove(index as _);
+    debug_assert_eq!(pair.0.into(), key);
+
+    if let Some((key, _)) = pairs.get(index as usize) {
+        let key: Key = (*key).into();
+        slots[key.index as usize].update(index);
+    }
+
+    if let Some(key) = key.increment() {
+        free.push(key);
+    }
+
+    Some(pair.1)
+}
+
+/// Moves the pairs for which `keep` returns `true` to the front of `pairs`, preserving their order, and removes the
+/// others, passing them to `removed`. `keep` receives the original index of each pair. Every step leaves `slots`
+/// consistent with `pairs` such that a panic in `keep` or `removed` does not corrupt the armoire.
+fn retain<T, K: KeyType>(
+    pairs: &mut Vec<Pair<T, K>>,
+    slots: &mut [Slot],
+    free: &mut Vec<Key>,
+    mut keep: impl FnMut(usize, &mut Pair<T, K>) -> bool,
+    removed: impl FnMut(K, T),
+) {
+    let mut count = 0;
+    for index in 0..pairs.len() {
+        if keep(index, &mut pairs[index]) {
+            if count < index {
+                pairs.swap(count, index);
+                let moved: Key = pairs[count].0.into();
+                slots[moved.index as usize].update(count as _);
+                let moved: Key = pairs[index].0.into();
+                slots[moved.index as usize].update(index as _);
             }
+            count += 1;
+        }
+    }
+    truncate(pairs, slots, free, count, removed);
+}
 
-            if let Some(key) = key.increment() {
-                free.push(key);
+/// Removes the pairs beyond `count`, passing them to `removed`.
+fn truncate<T, K: KeyType>(
+    pairs: &mut Vec<Pair<T, K>>,
+    slots: &mut [Slot],
+    free: &mut Vec<Key>,
+    count: usize,
+    mut removed: impl FnMut(K, T),
+) {
+    let count = count.min(pairs.len());
+    for (key, _) in pairs[count..].iter() {
+        let key: Key = (*key).into();
+        slots[key.index as usize].release(key.generation);
+        free.extend(key.increment());
+    }
+    for (key, value) in pairs.drain(count..) {
+        removed(key, value);
+    }
+}
+
+/// Removes the pairs at the unique `holes` indices in a single pass, passing them to `removed`. The slots of the
+/// removed pairs must already be released. Holes below the new length are filled with the surviving pairs beyond it,
+/// such that only those pairs move and `holes` does not need to be sorted.
+fn compact<T, K: KeyType>(
+    pairs: &mut Vec<Pair<T, K>>,
+    slots: &mut [Slot],
+    holes: &[usize],
+    mut removed: impl FnMut(K, T),
+) {
+    let count = pairs.len() - holes.len();
+    let mut source = pairs.len();
+    for &hole in holes.iter().filter(|&&hole| hole < count) {
+        let moved = loop {
+            source -= 1;
+            let key: Key = pairs[source].0.into();
+            if slots[key.index as usize].index < u32::MAX {
+                break key;
             }
+        };
+        pairs.swap(hole, source);
+        slots[moved.index as usize].update(hole as _);
+    }
+    for (key, value) in pairs.drain(count..) {
+        removed(key, value);
+    }
+}
 
-            Some(pair.1)
-        } else {
-            None
+/// Releases the reserved `key` if it has no value.
+fn discard(key: Key, tag: Tag, last: u32, slots: &mut [Slot], free: &mut Vec<Key>) -> bool {
+    if find(key, slots, last, tag) == Err(Error::Vacant)
+        && slots[key.index as usize].discard(key.generation)
+    {
+        free.extend(key.increment());
+        true
+    } else {
+        false
+    }
+}
+
+/// Drains `queue` in the order in which its operations must be applied; `key` extracts the key and sequence number
+/// of an operation.
+fn sequenced<T, K: Ord>(
+    queue: &mut Queue<T>,
+    order: Order,
+    conflict: Conflict,
+    key: impl Fn(&T) -> (K, u64),
+) -> Either<impl Iterator<Item = T> + '_, vec::IntoIter<T>> {
+    match (order, conflict) {
+        (Order::Arrival, Conflict::RemoveWins) => Either::Left(queue.drain()),
+        (Order::Arrival, Conflict::LastWins) => {
+            Either::Right(queue.sorted_by_key(|item| key(item).1))
         }
-    });
-    *cursor = free.len() as _;
-    values
+        (Order::Sorted, _) => Either::Right(queue.sorted_by_key(key)),
+    }
+}
+
+/// Drops the keys of `free` that have been taken by a [`Defer`] and merges the `pending` keys back in.
+#[inline]
+fn recycle(free: &mut Vec<Key>, pending: &mut Vec<Key>, cursor: i64) {
+    free.truncate(cursor.max(0) as usize);
+    free.append(pending);
+}
+
+#[inline]
+fn exhausted<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(error) => panic!("failed to reserve keys: {error}"),
+    }
 }
 
 #[inline]
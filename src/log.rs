@@ -0,0 +1,148 @@
+use crate::{Event, InsertError, Key};
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The events recorded by an armoire, kept until every [`Reader`] has read them.
+pub(crate) struct Log<T, K = Key> {
+    events: VecDeque<Event<T, K>>,
+    /// The position of the first event of `events` in the stream of all recorded events.
+    offset: u64,
+    /// The position of the next event to read for each reader; `u64::MAX` marks an unused cursor.
+    cursors: Vec<AtomicU64>,
+    readers: usize,
+    /// Identifies this log among all others such that readers of another log are rejected.
+    id: u64,
+    clone: fn(&T) -> T,
+}
+
+/// A cursor into the events recorded by an [`crate::Armoire`]. See [`crate::Armoire::subscribe`]. Using a reader with
+/// another armoire than the one it was subscribed to panics.
+#[derive(Debug)]
+pub struct Reader {
+    index: usize,
+    id: u64,
+}
+
+impl<T, K: Copy> Log<T, K> {
+    #[inline]
+    pub fn new(clone: fn(&T) -> T) -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self {
+            events: VecDeque::new(),
+            offset: 0,
+            cursors: Vec::new(),
+            readers: 0,
+            id: NEXT.fetch_add(1, Ordering::Relaxed),
+            clone,
+        }
+    }
+
+    /// Adds a reader that will read the events recorded from now on.
+    pub fn subscribe(&mut self) -> Reader {
+        let end = self.end();
+        self.readers += 1;
+        match self
+            .cursors
+            .iter_mut()
+            .position(|cursor| *cursor.get_mut() == u64::MAX)
+        {
+            Some(index) => {
+                *self.cursors[index].get_mut() = end;
+                Reader { index, id: self.id }
+            }
+            None => {
+                self.cursors.push(AtomicU64::new(end));
+                Reader {
+                    index: self.cursors.len() - 1,
+                    id: self.id,
+                }
+            }
+        }
+    }
+
+    pub fn unsubscribe(&mut self, reader: Reader) {
+        self.cursor(&reader);
+        *self.cursors[reader.index].get_mut() = u64::MAX;
+        self.readers -= 1;
+        self.trim();
+    }
+
+    /// Yields the events that `reader` has not read yet and marks them as read.
+    #[inline]
+    pub fn read(&self, reader: &mut Reader) -> impl ExactSizeIterator<Item = &Event<T, K>> {
+        let start = self.cursor(reader).swap(self.end(), Ordering::Relaxed);
+        self.events.range((start - self.offset) as usize..)
+    }
+
+    /// # Panics
+    /// If `reader` was not subscribed to this log.
+    #[inline]
+    fn cursor(&self, reader: &Reader) -> &AtomicU64 {
+        match self.cursors.get(reader.index) {
+            Some(cursor) if reader.id == self.id && cursor.load(Ordering::Relaxed) < u64::MAX => {
+                cursor
+            }
+            _ => panic!("reader was not subscribed to this armoire"),
+        }
+    }
+
+    /// Records `event` if there is at least one reader.
+    #[inline]
+    pub fn push(&mut self, event: Event<T, K>) {
+        if self.readers > 0 {
+            self.trim();
+            self.events.push_back(event);
+        }
+    }
+
+    /// Records a copy of `event` if there is at least one reader.
+    #[inline]
+    pub fn record(&mut self, event: &Event<T, K>) {
+        if self.readers > 0 {
+            let event = match event {
+                Event::Inserted(key) => Event::Inserted(*key),
+                Event::Removed(key, value) => Event::Removed(*key, (self.clone)(value)),
+                Event::Replaced(key, value) => Event::Replaced(*key, (self.clone)(value)),
+                Event::Released(key) => Event::Released(*key),
+                Event::Rejected(error) => Event::Rejected(InsertError {
+                    key: error.key,
+                    value: (self.clone)(&error.value),
+                    error: error.error,
+                }),
+            };
+            self.trim();
+            self.events.push_back(event);
+        }
+    }
+
+    /// Records the removal of a value that is handed back to the caller.
+    #[inline]
+    pub fn removed(&mut self, key: K, value: &T) {
+        if self.readers > 0 {
+            self.trim();
+            self.events
+                .push_back(Event::Removed(key, (self.clone)(value)));
+        }
+    }
+
+    /// Drops the events that every reader has read.
+    fn trim(&mut self) {
+        let end = self.end();
+        let start = self
+            .cursors
+            .iter_mut()
+            .map(|cursor| *cursor.get_mut())
+            .min()
+            .unwrap_or(u64::MAX)
+            .min(end);
+        self.events.drain(..(start - self.offset) as usize);
+        self.offset = start;
+    }
+
+    #[inline]
+    fn end(&self) -> u64 {
+        self.offset + self.events.len() as u64
+    }
+}
@@ -0,0 +1,83 @@
+use parking_lot::{Mutex, MutexGuard};
+use std::{num::NonZeroUsize, sync::OnceLock, thread::available_parallelism, vec};
+
+/// A queue that can be pushed to concurrently with little contention. Each rayon worker thread pushes to its own
+/// shard and the other threads share the first one. Shards are allocated on the first push.
+pub(crate) struct Queue<T> {
+    shards: OnceLock<Box<[Shard<T>]>>,
+}
+
+/// Aligned to its own cache line(s) to prevent false sharing between the threads that push to neighbouring shards.
+#[repr(align(128))]
+struct Shard<T>(Mutex<Vec<T>>);
+
+impl<T> Queue<T> {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            shards: OnceLock::new(),
+        }
+    }
+
+    /// Locks the shard of the current thread.
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, Vec<T>> {
+        let shards = self.shards.get_or_init(|| {
+            (0..count())
+                .map(|_| Shard(Mutex::new(Vec::new())))
+                .collect()
+        });
+        let index = rayon::current_thread_index().map_or(0, |index| index + 1);
+        shards[index % shards.len()].0.lock()
+    }
+
+    #[inline]
+    pub fn len(&mut self) -> usize {
+        self.shards().map(|shard| shard.len()).sum()
+    }
+
+    /// Removes the items of every shard, shard by shard. Items pushed from the same thread keep their order.
+    #[inline]
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.shards().flat_map(|shard| shard.drain(..))
+    }
+
+    #[inline]
+    pub fn iter(&mut self) -> impl Iterator<Item = &T> {
+        self.shards().flat_map(|shard| shard.iter())
+    }
+
+    /// Removes the items of every shard, stably sorted by `key`.
+    #[inline]
+    pub fn sorted_by_key<O: Ord>(&mut self, key: impl FnMut(&T) -> O) -> vec::IntoIter<T> {
+        let mut items = self.drain().collect::<Vec<_>>();
+        items.sort_by_key(key);
+        items.into_iter()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.shards().for_each(Vec::clear);
+    }
+
+    #[inline]
+    fn shards(&mut self) -> impl Iterator<Item = &mut Vec<T>> {
+        self.shards
+            .get_mut()
+            .into_iter()
+            .flat_map(|shards| shards.iter_mut().map(|shard| shard.0.get_mut()))
+    }
+}
+
+impl<T> Default for Queue<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One shard per available core, plus one for the threads outside of the rayon pool.
+fn count() -> usize {
+    static COUNT: OnceLock<usize> = OnceLock::new();
+    *COUNT.get_or_init(|| available_parallelism().map_or(1, NonZeroUsize::get) + 1)
+}
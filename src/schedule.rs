@@ -0,0 +1,277 @@
+use crate::{
+    join::Lookup, tick::Clock, Armoire, Defer, Fork, Key, KeyType, Pair, Project, Tracked, View,
+};
+use rayon::prelude::*;
+use std::marker::PhantomData;
+
+/// The fork through which a [`System`] accesses the view `V` of the values of an armoire, along with their key.
+pub type Access<'a, T, V, K = Key> = Fork<'a, Pair<T, K>, Tracking<'a, V>>;
+
+/// Projects a pair into its key and the view `V` of its value, which marks the value as changed when it is written.
+pub struct Tracking<'a, V>(Clock<'a>, PhantomData<fn() -> V>);
+
+/// A system run by a [`Schedule`], which declares the fields it accesses through the views generated with
+/// `#[derive(Fork)]`: the `Write` view is iterated mutably and the `Read` view is read for any key, typically to look
+/// up other values. Use `()` for a view that is not needed.
+pub trait System<T, R, K: KeyType = Key>: Send + Sync {
+    type Write<'a>: View<'a, T>
+    where
+        T: 'a,
+        K: 'a;
+    type Read<'a>: View<'a, T>
+    where
+        T: 'a,
+        K: 'a;
+
+    fn run<'a>(
+        &self,
+        writes: Access<'a, T, Self::Write<'a>, K>,
+        reads: Access<'a, T, Self::Read<'a>, K>,
+        resources: &R,
+        defer: &Defer<'a, T, K>,
+    );
+}
+
+/// Runs [`System`]s over the values of an [`Armoire`] with shared resources of type `R`. Systems run in the order
+/// they were added, except that consecutive systems whose views do not conflict run concurrently. A system conflicts
+/// with another if it writes a field that the other accesses. Operations deferred by the systems are resolved once,
+/// after every system ran.
+///
+/// ```
+/// use armoire::{Access, Armoire, Defer, Fork, Schedule, System};
+/// use rayon::prelude::*;
+///
+/// #[derive(Fork)]
+/// #[fork(Movement(mut position, velocity), Aging(mut age))]
+/// struct Body {
+///     position: f64,
+///     velocity: f64,
+///     age: u32,
+/// }
+///
+/// struct Move;
+/// struct Age;
+///
+/// impl System<Body, f64> for Move {
+///     type Write<'a> = Movement<'a>;
+///     type Read<'a> = ();
+///
+///     fn run<'a>(
+///         &self,
+///         mut movements: Access<'a, Body, Movement<'a>>,
+///         _: Access<'a, Body, ()>,
+///         delta: &f64,
+///         _: &Defer<'a, Body>,
+///     ) {
+///         movements
+///             .par_iter_mut()
+///             .for_each(|(_, movement)| *movement.position += movement.velocity * delta);
+///     }
+/// }
+///
+/// impl System<Body, f64> for Age {
+///     type Write<'a> = Aging<'a>;
+///     type Read<'a> = ();
+///
+///     fn run<'a>(
+///         &self,
+///         mut agings: Access<'a, Body, Aging<'a>>,
+///         _: Access<'a, Body, ()>,
+///         _: &f64,
+///         _: &Defer<'a, Body>,
+///     ) {
+///         agings.iter_mut().for_each(|(_, aging)| *aging.age += 1);
+///     }
+/// }
+///
+/// let mut bodies = Armoire::new();
+/// let key = bodies.insert(Body { position: 0.0, velocity: 2.0, age: 0 });
+/// let mut schedule = Schedule::new();
+/// // `Move` and `Age` do not conflict, such that they run concurrently.
+/// schedule.add(Move);
+/// schedule.add(Age);
+/// schedule.run(&mut bodies, &0.5);
+/// assert_eq!(bodies.get(key).map(|body| (body.position, body.age)), Some((1.0, 1)));
+/// ```
+pub struct Schedule<T, R, K = Key> {
+    systems: Vec<Box<dyn Run<T, R, K>>>,
+}
+
+/// A [`System`] whose views are erased.
+trait Run<T, R, K>: Send + Sync {
+    /// The fields read and written by the `Write` and `Read` views of the system.
+    fn views(&self) -> [(u64, u64); 2];
+
+    /// # Safety
+    /// `pairs` must be valid for `count` pairs for `'a` and their fields must not be borrowed elsewhere in a way that
+    /// conflicts with the views of the system.
+    unsafe fn run<'a>(
+        &self,
+        pairs: *mut Pair<T, K>,
+        count: usize,
+        lookup: Lookup<'a>,
+        clock: Clock<'a>,
+        resources: &R,
+        defer: &Defer<'a, T, K>,
+    );
+}
+
+/// Shares the pairs of an armoire with the systems that run concurrently.
+struct Pairs<T, K>(*mut Pair<T, K>);
+
+impl<T, R> Schedule<T, R> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_key()
+    }
+}
+
+impl<T, R, K: KeyType> Schedule<T, R, K> {
+    #[inline]
+    pub fn with_key() -> Self {
+        Self {
+            systems: Vec::new(),
+        }
+    }
+
+    /// Adds `system` after the systems already added. Fails to compile if a field is borrowed exclusively by one view
+    /// of the system and borrowed at all by the other.
+    ///
+    /// ```compile_fail
+    /// use armoire::{Access, Defer, Fork, Schedule, System};
+    ///
+    /// #[derive(Fork)]
+    /// #[fork(Movement(mut position), Sight(position))]
+    /// struct Body {
+    ///     position: f64,
+    /// }
+    ///
+    /// struct Conflicting;
+    ///
+    /// impl System<Body, ()> for Conflicting {
+    ///     type Write<'a> = Movement<'a>;
+    ///     type Read<'a> = Sight<'a>;
+    ///
+    ///     fn run<'a>(
+    ///         &self,
+    ///         _: Access<'a, Body, Movement<'a>>,
+    ///         _: Access<'a, Body, Sight<'a>>,
+    ///         _: &(),
+    ///         _: &Defer<'a, Body>,
+    ///     ) {
+    ///     }
+    /// }
+    ///
+    /// Schedule::new().add(Conflicting);
+    /// ```
+    pub fn add<S: System<T, R, K> + 'static>(&mut self, system: S) {
+        const {
+            assert!(
+                !conflicts(
+                    (S::Write::READS, S::Write::WRITES),
+                    (S::Read::READS, S::Read::WRITES)
+                ),
+                "the views of a system must not borrow the same field mutably"
+            )
+        };
+        self.systems.push(Box::new(system));
+    }
+
+    /// Runs every system over the values of `armoire`, then resolves the operations that they deferred.
+    pub fn run(&self, armoire: &mut Armoire<T, K>, resources: &R)
+    where
+        T: Send + Sync,
+        K: Send + Sync,
+        R: Sync,
+    {
+        let (mut pairs, defer) = armoire.defer();
+        let (pairs, lookup, clock) = pairs.parts();
+        let count = pairs.len();
+        let pairs = Pairs(pairs.as_mut_ptr());
+        let mut start = 0;
+        while start < self.systems.len() {
+            let mut end = start;
+            let mut batch = (0, 0);
+            while let Some(system) = self.systems.get(end) {
+                let access = access(system.views());
+                if conflicts(batch, access) {
+                    break;
+                }
+                batch = (batch.0 | access.0, batch.1 | access.1);
+                end += 1;
+            }
+            // The systems of a batch do not conflict, such that their views never alias.
+            self.systems[start..end]
+                .par_iter()
+                .for_each(|system| unsafe {
+                    system.run(pairs.get(), count, lookup, clock, resources, &defer)
+                });
+            start = end;
+        }
+        armoire.resolve();
+    }
+}
+
+impl<T, R, K: KeyType> Default for Schedule<T, R, K> {
+    fn default() -> Self {
+        Self::with_key()
+    }
+}
+
+impl<T, R, K: KeyType, S: System<T, R, K>> Run<T, R, K> for S {
+    #[inline]
+    fn views(&self) -> [(u64, u64); 2] {
+        [
+            (S::Write::READS, S::Write::WRITES),
+            (S::Read::READS, S::Read::WRITES),
+        ]
+    }
+
+    #[inline]
+    unsafe fn run<'a>(
+        &self,
+        pairs: *mut Pair<T, K>,
+        count: usize,
+        lookup: Lookup<'a>,
+        clock: Clock<'a>,
+        resources: &R,
+        defer: &Defer<'a, T, K>,
+    ) {
+        let writes = Fork::new(pairs, count, Tracking(clock, PhantomData), lookup);
+        let reads = Fork::new(pairs, count, Tracking(clock, PhantomData), lookup);
+        System::run(self, writes, reads, resources, defer);
+    }
+}
+
+impl<T, K> Pairs<T, K> {
+    #[inline]
+    fn get(&self) -> *mut Pair<T, K> {
+        self.0
+    }
+}
+
+unsafe impl<T: Send + Sync, K: Send + Sync> Sync for Pairs<T, K> {}
+
+impl<'a, T, K: KeyType, V: View<'a, T>> Project<Pair<T, K>> for Tracking<'a, V> {
+    type Item = Tracked<'a, (K, V)>;
+
+    #[inline]
+    unsafe fn project(&self, pair: *mut Pair<T, K>) -> Self::Item {
+        let key = (*pair).0;
+        self.0.track(key.into(), (key, V::view(&raw mut (*pair).1)))
+    }
+}
+
+/// Combines the fields read and written by the views of a system.
+#[inline]
+fn access([write, read]: [(u64, u64); 2]) -> (u64, u64) {
+    (write.0 | read.0, write.1 | read.1)
+}
+
+#[inline]
+const fn conflicts(
+    (left_reads, left_writes): (u64, u64),
+    (right_reads, right_writes): (u64, u64),
+) -> bool {
+    left_writes & (right_reads | right_writes) != 0 || right_writes & left_reads != 0
+}
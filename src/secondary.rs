@@ -0,0 +1,263 @@
+use crate::{
+    join::{Lookup, Mut, Ref},
+    tick::Clock,
+    Armoire, Error, InsertError, IntoSource, Key, KeyType,
+};
+use std::{collections::HashMap, mem::replace};
+
+/// Associates additional values with the keys of an [`crate::Armoire`], indexed densely by key index. Each index holds
+/// at most one value, such that inserting at a key of a newer generation overwrites the value of an outdated key and
+/// outdated keys no longer resolve. Prefer a [`SparseSecondaryMap`] when only few keys of the armoire have a value.
+///
+/// The map is not notified of removals from the armoire: the value of a removed key lingers and still resolves until
+/// its index is reused by a newer key. Call [`Self::sync`] after removals, or remove the values of the pairs reported
+/// by [`Armoire::resolve_with`], to drop them eagerly.
+pub struct SecondaryMap<V, K = Key> {
+    slots: Vec<u32>,
+    pairs: Vec<(K, V)>,
+}
+
+/// Same as [`SecondaryMap`], but indexes its values through a hash map such that its memory is proportional to its
+/// length rather than to the largest key index. Values of removed keys linger in the same way.
+pub struct SparseSecondaryMap<V, K = Key> {
+    slots: HashMap<u32, u32>,
+    pairs: Vec<(K, V)>,
+}
+
+/// Maps key indices to the position of their pair.
+trait Slots {
+    fn get(&self, index: u32) -> Option<u32>;
+    fn set(&mut self, index: u32, position: u32);
+    fn unset(&mut self, index: u32);
+}
+
+impl Slots for Vec<u32> {
+    #[inline]
+    fn get(&self, index: u32) -> Option<u32> {
+        self.as_slice()
+            .get(index as usize)
+            .copied()
+            .filter(|&position| position < u32::MAX)
+    }
+
+    #[inline]
+    fn set(&mut self, index: u32, position: u32) {
+        let index = index as usize;
+        if index >= self.len() {
+            self.resize(index + 1, u32::MAX);
+        }
+        self[index] = position;
+    }
+
+    #[inline]
+    fn unset(&mut self, index: u32) {
+        self[index as usize] = u32::MAX;
+    }
+}
+
+impl Slots for HashMap<u32, u32> {
+    #[inline]
+    fn get(&self, index: u32) -> Option<u32> {
+        HashMap::get(self, &index).copied()
+    }
+
+    #[inline]
+    fn set(&mut self, index: u32, position: u32) {
+        self.insert(index, position);
+    }
+
+    #[inline]
+    fn unset(&mut self, index: u32) {
+        self.remove(&index);
+    }
+}
+
+macro_rules! secondary {
+    ($name:ident, $slots:expr, $lookup:path) => {
+        impl<V> $name<V> {
+            #[inline]
+            pub fn new() -> Self {
+                Self::with_key()
+            }
+        }
+
+        impl<V, K: KeyType> $name<V, K> {
+            #[inline]
+            pub fn with_key() -> Self {
+                Self {
+                    slots: $slots,
+                    pairs: Vec::new(),
+                }
+            }
+
+            #[inline]
+            pub fn len(&self) -> usize {
+                self.pairs.len()
+            }
+
+            #[inline]
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+
+            #[inline]
+            pub fn has(&self, key: K) -> bool {
+                self.get(key).is_some()
+            }
+
+            #[inline]
+            pub fn get(&self, key: K) -> Option<&V> {
+                let position = find(key, &self.slots, &self.pairs)?;
+                Some(&self.pairs[position].1)
+            }
+
+            #[inline]
+            pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+                let position = find(key, &self.slots, &self.pairs)?;
+                Some(&mut self.pairs[position].1)
+            }
+
+            /// Inserts `value` at `key` and returns the previous value of `key`, if any. The value of an outdated key
+            /// at the same index is dropped. Fails if `key` is null or if the index already holds the value of a newer
+            /// key.
+            #[inline]
+            pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, InsertError<V, K>> {
+                insert(key, value, &mut self.slots, &mut self.pairs)
+            }
+
+            /// Removes and returns the value of `key`. The value of an outdated key at the same index is dropped.
+            #[inline]
+            pub fn remove(&mut self, key: K) -> Option<V> {
+                remove(key, &mut self.slots, &mut self.pairs)
+            }
+
+            pub fn retain<F: FnMut(K, &mut V) -> bool>(&mut self, mut keep: F) {
+                let mut position = 0;
+                while let Some((key, value)) = self.pairs.get_mut(position) {
+                    if keep(*key, value) {
+                        position += 1;
+                    } else {
+                        take(position, &mut self.slots, &mut self.pairs);
+                    }
+                }
+            }
+
+            /// Removes the values of the keys that are no longer in `armoire`.
+            #[inline]
+            pub fn sync<T>(&mut self, armoire: &Armoire<T, K>) {
+                self.retain(|key, _| armoire.has(key));
+            }
+
+            #[inline]
+            pub fn clear(&mut self) {
+                self.slots.clear();
+                self.pairs.clear();
+            }
+
+            #[inline]
+            pub fn iter(&self) -> impl Iterator<Item = (K, &V)> {
+                self.pairs.iter().map(|(key, value)| (*key, value))
+            }
+
+            #[inline]
+            pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut V)> {
+                self.pairs.iter_mut().map(|(key, value)| (*key, value))
+            }
+        }
+
+        impl<V, K: KeyType> Default for $name<V, K> {
+            fn default() -> Self {
+                Self::with_key()
+            }
+        }
+
+        impl<'a, V, K: KeyType> IntoSource for &'a $name<V, K> {
+            type Source = Ref<'a, K, V>;
+
+            #[inline]
+            fn into_source(self) -> Self::Source {
+                Ref::new($lookup(&self.slots), &self.pairs)
+            }
+        }
+
+        impl<'a, V, K: KeyType> IntoSource for &'a mut $name<V, K> {
+            type Source = Mut<'a, K, V>;
+
+            #[inline]
+            fn into_source(self) -> Self::Source {
+                Mut::new($lookup(&self.slots), &mut self.pairs, Clock::new(&[], 0))
+            }
+        }
+    };
+}
+
+secondary!(SecondaryMap, Vec::new(), Lookup::Indices);
+secondary!(SparseSecondaryMap, HashMap::new(), Lookup::Map);
+
+#[inline]
+fn find<K: KeyType, V>(key: K, slots: &impl Slots, pairs: &[(K, V)]) -> Option<usize> {
+    let position = slots.get(key.into().index)? as usize;
+    (pairs[position].0 == key).then_some(position)
+}
+
+fn insert<K: KeyType, V>(
+    key: K,
+    value: V,
+    slots: &mut impl Slots,
+    pairs: &mut Vec<(K, V)>,
+) -> Result<Option<V>, InsertError<V, K>> {
+    if key.into() == Key::NULL {
+        return Err(InsertError {
+            key,
+            value,
+            error: Error::Null,
+        });
+    }
+    let index = key.into().index;
+    match slots.get(index) {
+        Some(position) => {
+            let pair = &mut pairs[position as usize];
+            if generation(pair.0) > generation(key) {
+                Err(InsertError {
+                    key,
+                    value,
+                    error: Error::Stale,
+                })
+            } else if pair.0 == key {
+                Ok(Some(replace(&mut pair.1, value)))
+            } else {
+                *pair = (key, value);
+                Ok(None)
+            }
+        }
+        None => {
+            slots.set(index, pairs.len() as u32);
+            pairs.push((key, value));
+            Ok(None)
+        }
+    }
+}
+
+fn remove<K: KeyType, V>(key: K, slots: &mut impl Slots, pairs: &mut Vec<(K, V)>) -> Option<V> {
+    let position = slots.get(key.into().index)? as usize;
+    if generation(pairs[position].0) > generation(key) {
+        return None;
+    }
+    let (stored, value) = take(position, slots, pairs);
+    (stored == key).then_some(value)
+}
+
+/// Removes the pair at `position` and moves the last pair into its place.
+fn take<K: KeyType, V>(position: usize, slots: &mut impl Slots, pairs: &mut Vec<(K, V)>) -> (K, V) {
+    let pair = pairs.swap_remove(position);
+    slots.unset(pair.0.into().index);
+    if let Some((moved, _)) = pairs.get(position) {
+        slots.set((*moved).into().index, position as u32);
+    }
+    pair
+}
+
+#[inline]
+fn generation<K: KeyType>(key: K) -> u32 {
+    key.into().generation
+}
@@ -0,0 +1,342 @@
+use crate::{exhausted, queue::Queue, Armoire, Error, Event, Key, KeyType};
+use rayon::prelude::*;
+
+/// A row of a [`Table`], whose fields are each stored in their own column. Implemented for tuples of up to 6 fields.
+pub trait Row: Sized {
+    type Columns: Default;
+    type Ref<'a>
+    where
+        Self: 'a;
+    type Mut<'a>
+    where
+        Self: 'a;
+    type Slices<'a>
+    where
+        Self: 'a;
+    type SlicesMut<'a>
+    where
+        Self: 'a;
+
+    fn push(self, columns: &mut Self::Columns);
+    /// Removes the row at `index` of every column and moves the last row into its place.
+    fn swap_remove(columns: &mut Self::Columns, index: usize) -> Self;
+    fn swap(columns: &mut Self::Columns, left: usize, right: usize);
+    fn truncate(columns: &mut Self::Columns, len: usize);
+    fn get(columns: &Self::Columns, index: usize) -> Self::Ref<'_>;
+    fn get_mut(columns: &mut Self::Columns, index: usize) -> Self::Mut<'_>;
+    fn slices(columns: &Self::Columns) -> Self::Slices<'_>;
+    fn slices_mut(columns: &mut Self::Columns) -> Self::SlicesMut<'_>;
+    fn clear(columns: &mut Self::Columns);
+}
+
+/// Same as an [`Armoire`], but stores each field of its rows in a dense column of its own, such that iterating a
+/// column only touches the values of that column. All columns share the keys of one armoire and are kept in the same
+/// order.
+///
+/// ```
+/// let mut table = armoire::Table::new();
+/// let key = table.insert((1.0, 'a'));
+/// let (positions, names) = table.columns();
+/// assert_eq!(positions, [1.0]);
+/// assert_eq!(names, ['a']);
+/// assert_eq!(table.get(key), Some((&1.0, &'a')));
+/// ```
+pub struct Table<R: Row, K = Key> {
+    /// Maps keys to the index of their row.
+    rows: Armoire<u32, K>,
+    keys: Vec<K>,
+    columns: R::Columns,
+    inserts: Queue<(K, R)>,
+    removes: Queue<K>,
+}
+
+/// Defers operations on a [`Table`] until [`Table::resolve`] is called, like [`crate::Defer`] does for an
+/// [`Armoire`].
+pub struct TableDefer<'a, R, K = Key> {
+    rows: &'a Armoire<u32, K>,
+    inserts: &'a Queue<(K, R)>,
+    removes: &'a Queue<K>,
+}
+
+impl<R: Row> Table<R> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_key()
+    }
+}
+
+impl<R: Row, K: KeyType> Table<R, K> {
+    #[inline]
+    pub fn with_key() -> Self {
+        Self {
+            rows: Armoire::with_key(),
+            keys: Vec::new(),
+            columns: R::Columns::default(),
+            inserts: Queue::new(),
+            removes: Queue::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn has(&self, key: K) -> bool {
+        self.rows.has(key)
+    }
+
+    #[inline]
+    pub fn get(&self, key: K) -> Option<R::Ref<'_>> {
+        let &index = self.rows.get(key)?;
+        Some(R::get(&self.columns, index as usize))
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, key: K) -> Option<R::Mut<'_>> {
+        let &index = self.rows.get(key)?;
+        Some(R::get_mut(&mut self.columns, index as usize))
+    }
+
+    /// The keys of the rows, in the order of the columns.
+    #[inline]
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// The columns of the table, each as a slice in the order of [`Self::keys`].
+    #[inline]
+    pub fn columns(&self) -> R::Slices<'_> {
+        R::slices(&self.columns)
+    }
+
+    /// Splits the table into its keys and its columns, each of which can be mutated independently of the others.
+    #[inline]
+    pub fn fork(&mut self) -> (&[K], R::SlicesMut<'_>) {
+        (&self.keys, R::slices_mut(&mut self.columns))
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (K, R::Ref<'_>)> {
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(index, &key)| (key, R::get(&self.columns, index)))
+    }
+
+    /// # Panics
+    /// If key indices are exhausted.
+    #[inline]
+    pub fn insert(&mut self, row: R) -> K {
+        let key = self.rows.insert(self.keys.len() as u32);
+        self.keys.push(key);
+        row.push(&mut self.columns);
+        key
+    }
+
+    pub fn remove(&mut self, key: K) -> Result<R, Error> {
+        let index = self.rows.remove(key)? as usize;
+        self.keys.swap_remove(index);
+        if let Some(&moved) = self.keys.get(index) {
+            if let Some(row) = self.rows.get_mut(moved) {
+                *row = index as u32;
+            }
+        }
+        Ok(R::swap_remove(&mut self.columns, index))
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.rows.clear();
+        self.keys.clear();
+        R::clear(&mut self.columns);
+    }
+
+    /// Splits the table into its keys, its columns and a [`TableDefer`] that defers operations until the next
+    /// resolve.
+    #[inline]
+    pub fn defer(&mut self) -> (&[K], R::SlicesMut<'_>, TableDefer<'_, R, K>) {
+        let defer = TableDefer {
+            rows: &self.rows,
+            inserts: &self.inserts,
+            removes: &self.removes,
+        };
+        (&self.keys, R::slices_mut(&mut self.columns), defer)
+    }
+
+    /// Applies the operations deferred through [`TableDefer`], inserts before removes, and makes the reserved keys
+    /// available again. The removes are applied in one batch by the armoire of rows, after which the removed rows are
+    /// compacted in a single pass over the columns.
+    pub fn resolve(&mut self) {
+        for (key, row) in self.inserts.drain() {
+            if self.rows.try_insert(key, self.keys.len() as u32).is_ok() {
+                self.keys.push(key);
+                row.push(&mut self.columns);
+            }
+        }
+        let (_, defer) = self.rows.defer();
+        defer.remove(self.removes.drain());
+        let mut holes = Vec::new();
+        self.rows.resolve_with(|event| {
+            if let Event::Removed(_, row) = event {
+                holes.push(row as usize);
+            }
+        });
+        self.compact(&holes);
+    }
+
+    /// Removes the rows at the unique `holes` indices, whose keys must already be removed from the armoire of rows.
+    /// Holes below the new length are filled with the surviving rows beyond it, like the pairs of an [`Armoire`].
+    fn compact(&mut self, holes: &[usize]) {
+        let count = self.keys.len() - holes.len();
+        let mut source = self.keys.len();
+        for &hole in holes.iter().filter(|&&hole| hole < count) {
+            let moved = loop {
+                source -= 1;
+                if self.rows.has(self.keys[source]) {
+                    break self.keys[source];
+                }
+            };
+            self.keys.swap(hole, source);
+            R::swap(&mut self.columns, hole, source);
+            if let Some(row) = self.rows.get_mut(moved) {
+                *row = hole as u32;
+            }
+        }
+        self.keys.truncate(count);
+        R::truncate(&mut self.columns, count);
+    }
+}
+
+impl<R: Row, K: KeyType + Send + Sync> Table<R, K> {
+    /// Iterates the rows in parallel by zipping one parallel iterator per column, such that each column is only
+    /// touched through its own slice.
+    #[inline]
+    pub fn par_iter<'a>(&'a self) -> impl IndexedParallelIterator<Item = (K, R::Ref<'a>)>
+    where
+        R::Slices<'a>: IntoParallelIterator<Item = R::Ref<'a>, Iter: IndexedParallelIterator>,
+        R::Ref<'a>: Send,
+    {
+        self.keys.par_iter().copied().zip(R::slices(&self.columns))
+    }
+
+    /// Same as [`Self::par_iter`], but yields exclusive references to the fields of each row.
+    #[inline]
+    pub fn par_iter_mut<'a>(&'a mut self) -> impl IndexedParallelIterator<Item = (K, R::Mut<'a>)>
+    where
+        R::SlicesMut<'a>: IntoParallelIterator<Item = R::Mut<'a>, Iter: IndexedParallelIterator>,
+        R::Mut<'a>: Send,
+    {
+        self.keys
+            .par_iter()
+            .copied()
+            .zip(R::slices_mut(&mut self.columns))
+    }
+}
+
+impl<R: Row, K: KeyType> Default for Table<R, K> {
+    fn default() -> Self {
+        Self::with_key()
+    }
+}
+
+impl<R: Row, K: KeyType> TableDefer<'_, R, K> {
+    /// Reserves a key and defers the insertion of `row` until the next resolve.
+    ///
+    /// # Panics
+    /// If key indices are exhausted.
+    #[inline]
+    pub fn insert(&self, row: R) -> K {
+        let mut keys = [Key::NULL.into()];
+        exhausted(self.rows.reserve(&mut keys));
+        self.inserts.lock().push((keys[0], row));
+        keys[0]
+    }
+
+    /// Defers the removal of the rows of `keys` until the next resolve.
+    #[inline]
+    pub fn remove<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.removes.lock().extend(keys);
+    }
+}
+
+impl<R, K> Clone for TableDefer<'_, R, K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            rows: self.rows,
+            inserts: self.inserts,
+            removes: self.removes,
+        }
+    }
+}
+
+macro_rules! row {
+    ($($value:ident $index:tt),+) => {
+        impl<$($value),+> Row for ($($value,)+) {
+            type Columns = ($(Vec<$value>,)+);
+            type Ref<'a> = ($(&'a $value,)+) where Self: 'a;
+            type Mut<'a> = ($(&'a mut $value,)+) where Self: 'a;
+            type Slices<'a> = ($(&'a [$value],)+) where Self: 'a;
+            type SlicesMut<'a> = ($(&'a mut [$value],)+) where Self: 'a;
+
+            #[inline]
+            fn push(self, columns: &mut Self::Columns) {
+                $(columns.$index.push(self.$index);)+
+            }
+
+            #[inline]
+            fn swap_remove(columns: &mut Self::Columns, index: usize) -> Self {
+                ($(columns.$index.swap_remove(index),)+)
+            }
+
+            #[inline]
+            fn swap(columns: &mut Self::Columns, left: usize, right: usize) {
+                $(columns.$index.swap(left, right);)+
+            }
+
+            #[inline]
+            fn truncate(columns: &mut Self::Columns, len: usize) {
+                $(columns.$index.truncate(len);)+
+            }
+
+            #[inline]
+            fn get(columns: &Self::Columns, index: usize) -> Self::Ref<'_> {
+                ($(&columns.$index[index],)+)
+            }
+
+            #[inline]
+            fn get_mut(columns: &mut Self::Columns, index: usize) -> Self::Mut<'_> {
+                ($(&mut columns.$index[index],)+)
+            }
+
+            #[inline]
+            fn slices(columns: &Self::Columns) -> Self::Slices<'_> {
+                ($(columns.$index.as_slice(),)+)
+            }
+
+            #[inline]
+            fn slices_mut(columns: &mut Self::Columns) -> Self::SlicesMut<'_> {
+                ($(columns.$index.as_mut_slice(),)+)
+            }
+
+            #[inline]
+            fn clear(columns: &mut Self::Columns) {
+                $(columns.$index.clear();)+
+            }
+        }
+    };
+}
+
+row!(A 0);
+row!(A 0, B 1);
+row!(A 0, B 1, C 2);
+row!(A 0, B 1, C 2, D 3);
+row!(A 0, B 1, C 2, D 3, E 4);
+row!(A 0, B 1, C 2, D 3, E 4, F 5);
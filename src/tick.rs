@@ -0,0 +1,107 @@
+use crate::{Item, Key};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// The ticks at which the value of a slot was added and last changed.
+pub(crate) struct Ticks {
+    added: AtomicU32,
+    changed: AtomicU32,
+}
+
+/// Stamps the ticks of slots with the current tick. Its ticks are empty when change tracking is disabled, in which
+/// case stamping does nothing and every value is considered to have changed.
+#[derive(Clone, Copy)]
+pub(crate) struct Clock<'a> {
+    ticks: &'a [Ticks],
+    now: u32,
+}
+
+/// An [`Item`] of a [`struct@crate::Fork`] that marks the value of its key as changed when it is written.
+pub struct Tracked<'a, I> {
+    item: I,
+    key: Key,
+    clock: Clock<'a>,
+}
+
+impl Ticks {
+    #[inline]
+    pub fn new(tick: u32) -> Self {
+        Self {
+            added: AtomicU32::new(tick),
+            changed: AtomicU32::new(tick),
+        }
+    }
+}
+
+impl<'a> Clock<'a> {
+    #[inline]
+    pub fn new(ticks: &'a [Ticks], now: u32) -> Self {
+        Self { ticks, now }
+    }
+
+    #[inline]
+    pub fn add(self, key: Key) {
+        if let Some(ticks) = self.ticks.get(key.index as usize) {
+            ticks.added.store(self.now, Ordering::Relaxed);
+            ticks.changed.store(self.now, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn change(self, key: Key) {
+        if let Some(ticks) = self.ticks.get(key.index as usize) {
+            ticks.changed.store(self.now, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn added_since(self, key: Key, tick: u32) -> bool {
+        self.ticks
+            .get(key.index as usize)
+            .is_none_or(|ticks| ticks.added.load(Ordering::Relaxed) > tick)
+    }
+
+    #[inline]
+    pub fn changed_since(self, key: Key, tick: u32) -> bool {
+        self.ticks
+            .get(key.index as usize)
+            .is_none_or(|ticks| ticks.changed.load(Ordering::Relaxed) > tick)
+    }
+
+    #[inline]
+    pub fn track<I>(self, key: Key, item: I) -> Tracked<'a, I> {
+        Tracked {
+            item,
+            key,
+            clock: self,
+        }
+    }
+}
+
+impl<I: Item> Item for Tracked<'_, I> {
+    type Read = I::Read;
+    type Write<'b>
+        = I::Write<'b>
+    where
+        Self: 'b;
+
+    const EXCLUSIVE: bool = I::EXCLUSIVE;
+
+    fn read(self) -> Self::Read {
+        self.item.read()
+    }
+
+    fn write<'b>(self) -> Self::Write<'b>
+    where
+        Self: 'b,
+    {
+        self.clock.change(self.key);
+        self.item.write()
+    }
+
+    fn borrows(&self, borrows: &mut Vec<(Range<usize>, bool)>) {
+        self.item.borrows(borrows);
+    }
+}
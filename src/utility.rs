@@ -3,6 +3,7 @@ use std::iter::FusedIterator;
 pub trait FullIterator: Iterator + DoubleEndedIterator + ExactSizeIterator + FusedIterator {}
 impl<I: Iterator + DoubleEndedIterator + ExactSizeIterator + FusedIterator> FullIterator for I {}
 
+#[allow(dead_code)]
 pub trait IteratorExtensions: Iterator {
     #[inline]
     fn complete<C: FnMut(Self::Item)>(self, complete: C) -> Complete<Self, C>
@@ -14,6 +15,7 @@ pub trait IteratorExtensions: Iterator {
 }
 impl<I: Iterator> IteratorExtensions for I {}
 
+#[allow(dead_code)]
 pub struct Complete<I: Iterator, C: FnMut(I::Item)>(I, C);
 
 impl<I: Iterator, C: FnMut(I::Item)> Drop for Complete<I, C> {
@@ -1,6 +1,12 @@
 use armoire::*;
 use checkito::*;
-use std::{error, result};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    error,
+    panic::{self, AssertUnwindSafe},
+    result,
+};
 
 type Result = result::Result<(), Box<dyn error::Error>>;
 const COUNT: usize = 1024;
@@ -32,7 +38,7 @@ fn iter_has_inserted_key_value() -> Result {
         let key = armoire.insert(value);
         let pairs = armoire.iter().collect::<Vec<_>>();
         prove!(pairs.len() == 1)?;
-        prove!(pairs.get(0) == Some(&(key, &value)))
+        prove!(pairs.first() == Some(&(key, &value)))
     })?;
     Ok(())
 }
@@ -44,11 +50,420 @@ fn iter_mut_has_inserted_key_value() -> Result {
         let key = armoire.insert(value);
         let pairs = armoire.iter_mut().collect::<Vec<_>>();
         prove!(pairs.len() == 1)?;
-        prove!(pairs.get(0) == Some(&(key, &mut value)))
+        prove!(pairs.first() == Some(&(key, &mut value)))
     })?;
     Ok(())
 }
 
+new_key_type! {
+    struct EntityKey;
+}
+
+#[test]
+fn get_inserted_value_by_typed_key() -> Result {
+    u16::generator().check(COUNT, |&value| {
+        let mut armoire = Armoire::<_, EntityKey>::with_key();
+        let key = armoire.insert(value);
+        prove!(armoire.get(key) == Some(&value))?;
+        prove!(armoire.remove(key) == Ok(value))?;
+        prove!(armoire.get(key).is_none())
+    })?;
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "tag", not(all(feature = "tag-panic", debug_assertions))))]
+fn foreign_key_is_rejected() -> Result {
+    u8::generator().check(COUNT, |&value| {
+        let mut left = Armoire::new();
+        let mut right = Armoire::new();
+        let key = left.insert(value);
+        right.insert(value);
+        prove!(right.get(key).is_none())?;
+        prove!(!right.has(key))?;
+        prove!(right.remove(key) == Err(Error::Foreign))?;
+        prove!(right.try_insert(key, value).map_err(|error| error.error) == Err(Error::Foreign))?;
+        prove!(left.get(key) == Some(&value))
+    })?;
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "tag-panic", debug_assertions))]
+#[should_panic]
+fn foreign_key_panics() {
+    let mut left = Armoire::new();
+    let mut right = Armoire::new();
+    let key = left.insert(1u8);
+    right.insert(1u8);
+    right.get(key);
+}
+
+#[test]
+#[should_panic(expected = "reader was not subscribed to this armoire")]
+fn foreign_reader_panics() {
+    let mut left = Armoire::<u8>::new();
+    let mut right = Armoire::<u8>::new();
+    let mut reader = left.subscribe();
+    right.subscribe();
+    right.read(&mut reader).count();
+}
+
+#[test]
+fn entry_inserts_reserved_key() -> Result {
+    i32::generator().check(COUNT, |&value| {
+        let mut armoire = Armoire::new();
+        let Ok([key]) = armoire.reserve_n() else {
+            return prove!(false);
+        };
+        prove!(matches!(armoire.entry(key), Entry::Vacant(_)))?;
+        prove!(armoire.entry(key).or_insert(value).copied() == Some(value))?;
+        prove!(matches!(armoire.entry(key), Entry::Occupied(_)))?;
+        prove!(armoire.get(key) == Some(&value))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn entry_modifies_and_removes_occupied_key() -> Result {
+    i32::generator().check(COUNT, |&value| {
+        let mut armoire = Armoire::new();
+        let key = armoire.insert(value);
+        armoire.entry(key).and_modify(|value| *value = value.wrapping_add(1));
+        prove!(armoire.get(key) == Some(&value.wrapping_add(1)))?;
+        let Entry::Occupied(entry) = armoire.entry(key) else {
+            return prove!(false);
+        };
+        prove!(entry.remove() == value.wrapping_add(1))?;
+        prove!(matches!(armoire.entry(key), Entry::Stale(_)))?;
+        prove!(armoire.entry(key).or_insert(value).is_none())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn entry_removal_frees_key_index() {
+    let mut armoire = Armoire::new();
+    let mut secondary = SecondaryMap::new();
+    let mut key = armoire.insert(0);
+    for value in 1..100 {
+        secondary.insert(key, value).unwrap();
+        if let Entry::Occupied(entry) = armoire.entry(key) {
+            entry.remove();
+        }
+        key = armoire.insert(value);
+    }
+    // A key at the index of an outdated key drops its value, so a single value remains only if the index was reused.
+    secondary.insert(key, 0).unwrap();
+    assert_eq!(secondary.len(), 1);
+}
+
+#[test]
+fn pairs_entry_recycles_key_on_resolve() {
+    let mut armoire = Armoire::new();
+    let key = armoire.insert('a');
+    armoire.scope(|mut pairs, _| {
+        if let Entry::Occupied(entry) = pairs.entry(key) {
+            assert_eq!(entry.remove(), 'a');
+        }
+    });
+    assert!(armoire.is_empty());
+    assert_ne!(armoire.insert('b'), key);
+    assert_eq!(armoire.len(), 1);
+}
+
+#[test]
+fn get_many_mut_swaps_values() -> Result {
+    <(u8, u8)>::generator().check(COUNT, |&(left, right)| {
+        let mut armoire = Armoire::new();
+        let keys = armoire.insert_n([left, right]);
+        let Some([left_value, right_value]) = armoire.get_many_mut(keys) else {
+            return prove!(false);
+        };
+        std::mem::swap(left_value, right_value);
+        prove!(armoire.get(keys[0]) == Some(&right))?;
+        prove!(armoire.get(keys[1]) == Some(&left))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn try_get_many_mut_reports_key() {
+    let mut armoire = Armoire::new();
+    let [left, right] = armoire.insert_n(['a', 'b']);
+    assert_eq!(
+        armoire.try_get_many_mut([left, right, left]).err(),
+        Some((left, Error::Duplicate))
+    );
+    assert_eq!(armoire.remove(right), Ok('b'));
+    assert_eq!(
+        armoire.try_get_many_mut([left, right]).err(),
+        Some((right, Error::Stale))
+    );
+    assert_eq!(
+        armoire.try_get_many_mut([Key::NULL]).err(),
+        Some((Key::NULL, Error::Null))
+    );
+    let [reserved] = armoire.reserve_n().unwrap();
+    assert_eq!(
+        armoire.try_get_many_mut([reserved]).err(),
+        Some((reserved, Error::Vacant))
+    );
+}
+
+#[test]
+fn try_insert_reports_error() {
+    let mut armoire = Armoire::new();
+    let key = armoire.insert('a');
+    let error = armoire.try_insert(key, 'b').unwrap_err();
+    assert_eq!((error.key, error.value, error.error), (key, 'b', Error::Occupied));
+    assert_eq!(armoire.remove(key), Ok('a'));
+    assert_eq!(armoire.remove(key), Err(Error::Stale));
+    let error = armoire.try_insert(key, 'c').unwrap_err();
+    assert_eq!(error.error, Error::Stale);
+    let error = armoire.try_insert(Key::NULL, 'd').unwrap_err();
+    assert_eq!(error.error, Error::Null);
+    let [reserved] = armoire.reserve_n().unwrap();
+    assert_eq!(armoire.remove(reserved), Err(Error::Vacant));
+    assert_eq!(armoire.try_insert(reserved, 'e'), Ok(()));
+    assert_eq!(armoire.get(reserved), Some(&'e'));
+}
+
+#[test]
+fn defer_try_insert_rejects_unreserved_key() {
+    let mut armoire = Armoire::<char>::new();
+    let (_, defer) = armoire.defer();
+    let [reserved] = defer.insert_n(['a']);
+    let error = defer.try_insert(Key::NULL, 'b').unwrap_err();
+    assert_eq!(error.error, Error::Null);
+    assert!(defer.try_insert(reserved, 'c').is_ok());
+}
+
+#[test]
+fn resolve_with_reports_events() {
+    let mut armoire = Armoire::new();
+    let occupied = armoire.insert('a');
+    let (_, defer) = armoire.defer();
+    let inserted = defer.insert('b');
+    assert!(defer.try_insert(occupied, 'c').is_ok());
+    defer.remove([occupied, occupied]);
+    let mut events = Vec::new();
+    armoire.resolve_with(|event| events.push(event));
+    assert_eq!(
+        events,
+        [
+            Event::Inserted(inserted),
+            Event::Rejected(InsertError {
+                key: occupied,
+                value: 'c',
+                error: Error::Occupied
+            }),
+            Event::Removed(occupied, 'a'),
+        ]
+    );
+    assert_eq!(armoire.get(inserted), Some(&'b'));
+}
+
+#[test]
+fn sorted_order_ignores_deferral_order() -> Result {
+    <Vec<(u16, bool)>>::generator().check(COUNT, |pairs| {
+        let resolve = |reverse: bool| {
+            let mut armoire = pairs
+                .iter()
+                .map(|&(value, _)| value)
+                .collect::<Armoire<_>>();
+            armoire.set_order(Order::Sorted);
+            let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+            let reserved = armoire.reserve_n::<4>().unwrap();
+            let (_, defer) = armoire.defer();
+            let mut removes = keys
+                .iter()
+                .zip(pairs)
+                .filter(|(_, &(_, remove))| remove)
+                .map(|(&key, _)| key)
+                .collect::<Vec<_>>();
+            let mut inserts = reserved.iter().copied().zip(1000..).collect::<Vec<_>>();
+            if reverse {
+                removes.reverse();
+                inserts.reverse();
+            }
+            defer.remove(removes);
+            for (key, value) in inserts {
+                assert!(defer.try_insert(key, value).is_ok());
+            }
+            armoire.resolve();
+            armoire.iter().map(|(_, &value)| value).collect::<Vec<_>>()
+        };
+        prove!(resolve(false) == resolve(true))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn tracking_reports_added_and_changed_keys() {
+    let mut armoire = Armoire::new();
+    armoire.set_tracking(true);
+    let [a, b] = armoire.insert_n([1, 2]);
+    let since = armoire.tick();
+    assert_eq!(armoire.iter_changed_since(since).count(), 0);
+    *armoire.get_mut(a).unwrap() += 1;
+    assert_eq!(
+        armoire.iter_changed_since(since).collect::<Vec<_>>(),
+        [(a, &2)]
+    );
+    assert_eq!(armoire.iter_added_since(since).count(), 0);
+
+    let since = armoire.tick();
+    {
+        let (values, keys) = armoire.fork(|_, value| unsafe { &*value }, |key, _| key);
+        assert_eq!(values.iter().count(), keys.iter().count());
+    }
+    assert_eq!(armoire.iter_changed_since(since).count(), 0);
+    {
+        let (mut values, _) = armoire.fork(|_, value| unsafe { &mut *value }, |key, _| key);
+        values.iter_mut().for_each(|value| *value += 1);
+    }
+    assert_eq!(armoire.iter_changed_since(since).count(), 2);
+
+    let since = armoire.tick();
+    let c = armoire.scope(|mut pairs, defer| {
+        pairs.par_iter_mut().for_each(|(key, value)| {
+            if key == b {
+                *value += 1;
+            }
+        });
+        defer.modify(a, |value| *value += 1);
+        defer.insert(4)
+    });
+    assert_eq!(
+        armoire.iter_added_since(since).collect::<Vec<_>>(),
+        [(c, &4)]
+    );
+    assert_eq!(armoire.iter_changed_since(since).count(), 3);
+
+    armoire.set_tracking(false);
+    let since = armoire.tick();
+    assert_eq!(armoire.iter_changed_since(since).count(), 3);
+}
+
+#[test]
+fn fork_gets_values_by_key() -> Result {
+    <Vec<i16>>::generator().check(COUNT, |values| {
+        let mut armoire = values.iter().copied().collect::<Armoire<_>>();
+        let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        {
+            let (mut lefts, rights) = armoire.fork(|_, value| unsafe { &mut *value }, |key, _| key);
+            for &key in &keys {
+                prove!(rights.get(key) == Some(key))?;
+                let left = lefts.get_mut(key).unwrap();
+                *left = left.wrapping_neg();
+            }
+        }
+        {
+            let (lefts, _) = armoire.fork(|_, value| unsafe { &*value }, |key, _| key);
+            for (&key, value) in keys.iter().zip(values) {
+                prove!(lefts.get(key) == Some(&value.wrapping_neg()))?;
+            }
+        }
+        if let Some(&key) = keys.first() {
+            armoire.remove(key).unwrap();
+        }
+        let (_, rights) = armoire.fork(|_, value| unsafe { &*value }, |key, _| key);
+        prove!(keys.first().and_then(|&key| rights.get(key)).is_none())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn readers_drain_events_independently() {
+    let mut armoire = Armoire::new();
+    let old = armoire.insert('a');
+    let mut first = armoire.subscribe();
+    let [key] = armoire.insert_n(['b']);
+    let mut second = armoire.subscribe();
+    assert_eq!(
+        armoire.read(&mut first).collect::<Vec<_>>(),
+        [&Event::Inserted(key)]
+    );
+    assert_eq!(armoire.read(&mut first).count(), 0);
+
+    let [reserved] = armoire.reserve_n().unwrap();
+    armoire.release([reserved]);
+    assert_eq!(armoire.remove(key), Ok('b'));
+    let deferred = armoire.scope(|_, defer| {
+        defer.remove([old]);
+        defer.insert('c')
+    });
+    let events = [
+        Event::Released(reserved),
+        Event::Removed(key, 'b'),
+        Event::Inserted(deferred),
+        Event::Removed(old, 'a'),
+    ];
+    assert!(armoire.read(&mut first).eq(&events));
+    armoire.unsubscribe(first);
+    armoire.retain(|_, _| false);
+    assert!(armoire
+        .read(&mut second)
+        .eq(events.iter().chain([&Event::Removed(deferred, 'c')])));
+}
+
+#[test]
+fn defer_modify_applies_between_inserts_and_removes() {
+    let mut armoire = Armoire::new();
+    let a = armoire.insert(1);
+    let b = armoire.insert(2);
+    let (pairs, defer) = armoire.defer();
+    pairs.par_iter().for_each(|(key, &value)| {
+        if key == b {
+            defer.modify(a, move |target| *target += value * 10);
+        }
+    });
+    let c = defer.insert(3);
+    defer.modify(c, |value| *value *= 2);
+    defer.replace(b, 5);
+    defer.remove([b]);
+    let mut events = Vec::new();
+    armoire.resolve_with(|event| events.push(event));
+    assert_eq!(
+        events,
+        [
+            Event::Inserted(c),
+            Event::Replaced(b, 2),
+            Event::Removed(b, 5)
+        ]
+    );
+    assert_eq!(armoire.get(a), Some(&21));
+    assert_eq!(armoire.get(c), Some(&6));
+
+    let (_, defer) = armoire.defer();
+    defer.modify(b, |_| panic!("the value of a removed key was modified"));
+    armoire.resolve();
+}
+
+#[test]
+fn scope_modify_borrows_from_outside_of_scope() {
+    let mut armoire = Armoire::new();
+    let key = armoire.insert(1);
+    let values = vec![2, 3];
+    armoire.scope(|_, defer| {
+        for value in &values {
+            defer.modify(key, move |target| *target += value);
+        }
+    });
+    assert_eq!(armoire.get(key), Some(&6));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        armoire.scope(|_, defer| {
+            defer.modify(key, |target| *target += values[0]);
+            panic!();
+        })
+    }));
+    assert!(result.is_err());
+    armoire.resolve();
+    assert_eq!(armoire.get(key), Some(&6));
+}
+
 // #[test]
 // fn scope_writes_twice() {
 //     let mut armoire = Armoire::new();
@@ -60,12 +475,864 @@ fn iter_mut_has_inserted_key_value() -> Result {
 //     });
 // }
 
+#[test]
+fn clear_invalidates_reserved_keys() {
+    let mut armoire = Armoire::new();
+    let key = armoire.insert(1);
+    let [reserved] = armoire.reserve_n().unwrap();
+    armoire.clear();
+    assert!(armoire.is_empty());
+    assert_eq!(armoire.get(key), None);
+    assert_eq!(armoire.try_insert(reserved, 2).map_err(|error| error.error), Err(Error::Stale));
+}
+
+#[test]
+fn drain_yields_pairs() -> Result {
+    <Vec<u8>>::generator().check(COUNT, |values| {
+        let mut armoire = values.iter().copied().collect::<Armoire<_>>();
+        let pairs = armoire.iter().map(|(key, &value)| (key, value)).collect::<Vec<_>>();
+        prove!(armoire.drain().collect::<Vec<_>>() == pairs)?;
+        prove!(armoire.is_empty())?;
+        prove!(pairs.iter().all(|&(key, _)| !armoire.has(key)))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn retain_keeps_matching_pairs() -> Result {
+    <Vec<u8>>::generator().check(COUNT, |values| {
+        let mut armoire = Armoire::new();
+        let keys = armoire.insert_iter(values.iter().copied());
+        armoire.retain(|_, value| *value % 2 == 0);
+        prove!(armoire.len() == values.iter().filter(|&value| value % 2 == 0).count())?;
+        prove!(keys
+            .iter()
+            .zip(values)
+            .all(|(&key, value)| armoire.has(key) == (value % 2 == 0)))?;
+        prove!(armoire.iter().all(|(key, value)| armoire.get(key) == Some(value)))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn truncate_removes_last_pairs() -> Result {
+    <(Vec<u8>, usize)>::generator().check(COUNT, |(values, len)| {
+        let mut armoire = Armoire::new();
+        let keys = armoire.insert_iter(values.iter().copied());
+        let len = len % (values.len() + 1);
+        armoire.truncate(len);
+        prove!(armoire.len() == len)?;
+        prove!(keys.iter().enumerate().all(|(index, &key)| armoire.has(key) == (index < len)))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn par_retain_matches_retain() -> Result {
+    <Vec<u16>>::generator().check(COUNT, |values| {
+        let mut left = values.iter().copied().collect::<Armoire<_>>();
+        let mut right = values.iter().copied().collect::<Armoire<_>>();
+        // Keys carry the tag of their armoire, such that the keys of `left` are mapped to those of `right`.
+        let keys = left
+            .iter()
+            .zip(right.iter())
+            .map(|((left, _), (right, _))| (left, right))
+            .collect::<HashMap<_, _>>();
+        left.retain(|_, value| *value % 3 == 0);
+        right.par_retain(|_, value| *value % 3 == 0);
+        prove!(left
+            .iter()
+            .map(|(key, value)| (keys[&key], value))
+            .eq(right.iter()))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn par_drain_filter_yields_removed_pairs() -> Result {
+    <Vec<u16>>::generator().check(COUNT, |values| {
+        let mut armoire = values.iter().copied().collect::<Armoire<_>>();
+        let mut expected = armoire
+            .iter()
+            .filter(|(_, value)| *value % 3 == 0)
+            .map(|(key, &value)| (key, value))
+            .collect::<Vec<_>>();
+        let mut drained = armoire
+            .par_drain_filter(|_, value| *value % 3 == 0)
+            .collect::<Vec<_>>();
+        drained.sort();
+        expected.sort();
+        prove!(drained == expected)?;
+        prove!(armoire.len() + drained.len() == values.len())?;
+        prove!(drained.iter().all(|&(key, _)| !armoire.has(key)))?;
+        prove!(armoire.iter().all(|(_, value)| value % 3 != 0))
+    })?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
 pub enum Action {
     Insert(usize, bool),
+    TryInsert(usize, usize),
     Remove(usize, bool),
+    Reserve,
+    Release(usize),
+    Retain(bool),
     Clear,
     Drain,
     Resolve,
 }
 
-pub fn model() {}
+enum Deferred {
+    Insert(Key, usize),
+    Remove(Key),
+}
+
+struct Model {
+    armoire: Armoire<usize>,
+    conflict: Conflict,
+    values: Vec<(Key, usize)>,
+    reserved: Vec<Key>,
+    dead: Vec<Key>,
+    deferred: Vec<Deferred>,
+}
+
+impl Action {
+    fn generator() -> impl Generate<Item = Self> {
+        <(u8, usize, usize, bool)>::generator().map(|(kind, index, value, defer)| match kind % 9 {
+            0 => Action::Insert(value, defer),
+            1 => Action::TryInsert(index, value),
+            2 => Action::Remove(index, defer),
+            3 => Action::Reserve,
+            4 => Action::Release(index),
+            5 => Action::Retain(defer),
+            6 => Action::Clear,
+            7 => Action::Drain,
+            _ => Action::Resolve,
+        })
+    }
+}
+
+impl Model {
+    fn new(conflict: Conflict) -> Self {
+        let mut armoire = Armoire::new();
+        armoire.set_conflict(conflict);
+        Self {
+            armoire,
+            conflict,
+            values: Vec::new(),
+            reserved: Vec::new(),
+            dead: Vec::new(),
+            deferred: Vec::new(),
+        }
+    }
+
+    fn key(&self, index: usize) -> Option<Key> {
+        let keys = self.values.iter().map(|&(key, _)| key);
+        let keys = keys.chain(self.reserved.iter().copied());
+        let keys = keys.chain(self.dead.iter().copied()).collect::<Vec<_>>();
+        keys.get(index % keys.len().max(1)).copied()
+    }
+
+    fn live(&self, key: Key) -> Option<usize> {
+        self.values.iter().position(|&(live, _)| live == key)
+    }
+
+    fn reserved(&self, key: Key) -> Option<usize> {
+        self.reserved.iter().position(|&reserved| reserved == key)
+    }
+
+    fn invalidate(&mut self) {
+        self.dead.extend(self.values.drain(..).map(|(key, _)| key));
+        self.dead.append(&mut self.reserved);
+        self.deferred.clear();
+    }
+
+    fn insert(&mut self, key: Key, value: usize) {
+        if let Some(position) = self.reserved(key) {
+            self.reserved.remove(position);
+            self.values.push((key, value));
+        } else if let Some(position) = self.live(key) {
+            if self.conflict == Conflict::LastWins {
+                self.values[position].1 = value;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: Key, vacate: bool) {
+        if let Some(position) = self.live(key) {
+            self.values.remove(position);
+            if vacate {
+                self.reserved.push(key);
+            } else {
+                self.dead.push(key);
+            }
+        } else if let Some(position) = self.reserved(key) {
+            if !vacate {
+                self.reserved.remove(position);
+                self.dead.push(key);
+            }
+        }
+    }
+
+    fn resolve(&mut self) {
+        self.armoire.resolve();
+        let deferred = std::mem::take(&mut self.deferred);
+        match self.conflict {
+            Conflict::RemoveWins => {
+                for deferred in deferred.iter() {
+                    if let &Deferred::Insert(key, value) = deferred {
+                        self.insert(key, value);
+                    }
+                }
+                for deferred in deferred.iter() {
+                    if let &Deferred::Remove(key) = deferred {
+                        self.remove(key, false);
+                    }
+                }
+            }
+            Conflict::LastWins => {
+                for (index, operation) in deferred.iter().enumerate() {
+                    match *operation {
+                        Deferred::Insert(key, value) => self.insert(key, value),
+                        Deferred::Remove(key) => {
+                            let vacate = deferred_insert(&deferred[index + 1..], key);
+                            self.remove(key, vacate);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, action: &Action) {
+        match *action {
+            Action::Insert(value, false) => {
+                let key = self.armoire.insert(value);
+                self.values.push((key, value));
+            }
+            Action::Insert(value, true) => {
+                let (_, defer) = self.armoire.defer();
+                let key = defer.insert(value);
+                self.reserved.push(key);
+                self.deferred.push(Deferred::Insert(key, value));
+            }
+            Action::TryInsert(index, value) => {
+                let Some(key) = self.key(index) else { return };
+                let (_, defer) = self.armoire.defer();
+                assert!(defer.try_insert(key, value).is_ok());
+                self.deferred.push(Deferred::Insert(key, value));
+            }
+            Action::Remove(index, false) => {
+                let Some(key) = self.key(index) else { return };
+                let removed = self.armoire.remove(key).ok();
+                let position = self.live(key);
+                assert_eq!(removed, position.map(|position| self.values[position].1));
+                if let Some(position) = position {
+                    self.values.remove(position);
+                    self.dead.push(key);
+                }
+            }
+            Action::Remove(index, true) => {
+                let Some(key) = self.key(index) else { return };
+                let (_, defer) = self.armoire.defer();
+                defer.remove([key]);
+                self.deferred.push(Deferred::Remove(key));
+            }
+            Action::Reserve => {
+                let [key] = self.armoire.reserve_n().unwrap();
+                self.reserved.push(key);
+            }
+            Action::Release(index) => {
+                let Some(key) = self.key(index) else { return };
+                self.armoire.release([key]);
+                if let Some(position) = self.reserved(key) {
+                    self.reserved.remove(position);
+                    self.dead.push(key);
+                }
+            }
+            Action::Retain(even) => {
+                self.armoire.retain(|_, value| (*value % 2 == 0) == even);
+                let (values, dead) = self
+                    .values
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|(_, value)| (*value % 2 == 0) == even);
+                self.values = values;
+                self.dead.extend(dead.into_iter().map(|(key, _)| key));
+            }
+            Action::Clear => {
+                self.armoire.clear();
+                self.invalidate();
+            }
+            Action::Drain => {
+                let mut drained = self.armoire.drain().collect::<Vec<_>>();
+                let mut values = self.values.clone();
+                drained.sort();
+                values.sort();
+                assert_eq!(drained, values);
+                self.invalidate();
+            }
+            Action::Resolve => self.resolve(),
+        }
+    }
+
+    fn verify(&self) -> result::Result<(), prove::Error> {
+        prove!(self.armoire.len() == self.values.len())?;
+        for &(key, value) in self.values.iter() {
+            prove!(self.armoire.get(key) == Some(&value))?;
+        }
+        for &key in self.reserved.iter().chain(&self.dead) {
+            prove!(!self.armoire.has(key))?;
+        }
+        for (key, value) in self.armoire.iter() {
+            prove!(self.values.contains(&(key, *value)))?;
+        }
+        Ok(())
+    }
+}
+
+fn deferred_insert(deferred: &[Deferred], key: Key) -> bool {
+    deferred
+        .iter()
+        .any(|deferred| matches!(*deferred, Deferred::Insert(insert, _) if insert == key))
+}
+
+fn model(conflict: Conflict) -> Result {
+    Action::generator()
+        .collect::<Vec<_>>()
+        .check(COUNT, |actions| {
+            let mut model = Model::new(conflict);
+            for action in actions {
+                model.apply(action);
+                model.verify()?;
+            }
+            Ok::<_, prove::Error>(())
+        })?;
+    Ok(())
+}
+
+#[test]
+fn model_remove_wins() -> Result {
+    model(Conflict::RemoveWins)
+}
+
+#[test]
+fn model_last_wins() -> Result {
+    model(Conflict::LastWins)
+}
+
+#[test]
+fn hooks_observe_inserts_and_removes() {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    let mut armoire = Armoire::new();
+    let keys = Arc::new(Mutex::new(HashSet::new()));
+    let inserted = keys.clone();
+    armoire.on_insert(move |key, value: &mut i32, defer| {
+        inserted.lock().unwrap().insert(key);
+        if *value < 0 {
+            defer.remove([key]);
+        }
+    });
+    let removed = keys.clone();
+    armoire.on_remove(move |key, _, _| assert!(removed.lock().unwrap().remove(&key)));
+
+    let [a, b] = armoire.insert_n([1, -2]);
+    assert_eq!(*keys.lock().unwrap(), HashSet::from([a, b]));
+    assert_eq!(armoire.remove(a), Ok(1));
+    let c = armoire.scope(|_, defer| defer.insert(3));
+    assert_eq!(*keys.lock().unwrap(), HashSet::from([c]));
+    assert_eq!(armoire.get(b), None);
+
+    let d = armoire.scope(|_, defer| defer.insert(-4));
+    assert_eq!(armoire.get(d), Some(&-4));
+    armoire.resolve();
+    assert_eq!(armoire.get(d), None);
+    assert_eq!(*keys.lock().unwrap(), HashSet::from([c]));
+}
+
+#[test]
+fn hooks_observe_bulk_removals() -> Result {
+    use std::sync::{Arc, Mutex};
+
+    <Vec<i16>>::generator().check(COUNT, |values| {
+        let mut armoire = values.iter().copied().collect::<Armoire<_>>();
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        let hooked = removed.clone();
+        armoire
+            .on_remove(move |key, value: &mut i16, _| hooked.lock().unwrap().push((key, *value)));
+        let pairs = |armoire: &Armoire<i16>, remove: &dyn Fn(usize, i16) -> bool| {
+            armoire
+                .iter()
+                .enumerate()
+                .filter(|&(index, (_, &value))| remove(index, value))
+                .map(|(_, (key, &value))| (key, value))
+                .collect::<Vec<_>>()
+        };
+        let observed = || {
+            let mut pairs = removed.lock().unwrap().drain(..).collect::<Vec<_>>();
+            pairs.sort_unstable();
+            pairs
+        };
+
+        let mut expected = pairs(&armoire, &|_, value| value % 2 != 0);
+        armoire.retain(|_, value| *value % 2 == 0);
+        expected.sort_unstable();
+        prove!(observed() == expected)?;
+
+        let mut expected = pairs(&armoire, &|_, value| value % 3 == 0);
+        let mut drained = armoire
+            .par_drain_filter(|_, value| *value % 3 == 0)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        drained.sort_unstable();
+        prove!(drained == expected)?;
+        prove!(observed() == expected)?;
+
+        let mut expected = pairs(&armoire, &|index, _| index >= 1);
+        armoire.truncate(1);
+        expected.sort_unstable();
+        prove!(observed() == expected)?;
+
+        let expected = pairs(&armoire, &|_, _| true);
+        armoire.clear();
+        prove!(observed() == expected)
+    })?;
+    Ok(())
+}
+
+#[test]
+fn hooks_and_readers_observe_entries() {
+    use std::sync::{Arc, Mutex};
+
+    let mut armoire = Armoire::new();
+    let mut reader = armoire.subscribe();
+    let hooked = Arc::new(Mutex::new(Vec::new()));
+    let inserted = hooked.clone();
+    armoire.on_insert(move |key, value: &mut char, _| {
+        inserted.lock().unwrap().push(Event::Inserted(key));
+        *value = value.to_ascii_uppercase();
+    });
+    let removed = hooked.clone();
+    armoire.on_remove(move |key, value: &mut char, _| {
+        removed.lock().unwrap().push(Event::Removed(key, *value))
+    });
+
+    let [a, b] = armoire.reserve_n().unwrap();
+    assert_eq!(armoire.entry(a).or_insert('a'), Some(&mut 'A'));
+    if let Entry::Occupied(entry) = armoire.entry(a) {
+        assert_eq!(entry.remove(), 'A');
+    }
+    armoire.scope(|mut pairs, _| {
+        pairs.entry(b).or_insert('b');
+        if let Entry::Occupied(entry) = pairs.entry(b) {
+            assert_eq!(entry.remove(), 'B');
+        }
+    });
+    let events = [
+        Event::Inserted(a),
+        Event::Removed(a, 'A'),
+        Event::Inserted(b),
+        Event::Removed(b, 'B'),
+    ];
+    assert_eq!(*hooked.lock().unwrap(), events);
+    assert!(armoire.read(&mut reader).eq(&events));
+}
+
+#[test]
+fn secondary_maps_follow_armoire_keys() -> Result {
+    <Vec<(u8, bool)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = Armoire::new();
+        let mut dense = SecondaryMap::new();
+        let mut sparse = SparseSecondaryMap::new();
+        let mut old = Vec::new();
+        for &(value, remove) in pairs {
+            let key = armoire.insert(value);
+            assert_eq!(dense.insert(key, value), Ok(None));
+            assert_eq!(sparse.insert(key, value), Ok(None));
+            if remove {
+                assert_eq!(armoire.remove(key), Ok(value));
+                old.push(key);
+            }
+        }
+        // Keys reinserted at the indices of removed keys invalidate their values.
+        let new = armoire.insert_iter(old.iter().map(|_| u8::MAX));
+        for &key in &new {
+            dense.insert(key, u8::MAX).unwrap();
+            sparse.insert(key, u8::MAX).unwrap();
+        }
+        for &key in &old {
+            prove!(dense.get(key).is_none())?;
+            prove!(sparse.get(key).is_none())?;
+            prove!(dense.insert(key, 0).is_err())?;
+            prove!(sparse.insert(key, 0).is_err())?;
+        }
+        prove!(dense.len() == armoire.len())?;
+        prove!(sparse.len() == armoire.len())?;
+        for (key, value) in armoire.iter() {
+            prove!(dense.get(key) == Some(value))?;
+            prove!(sparse.get(key) == Some(value))?;
+        }
+        dense.retain(|_, value| *value % 2 == 0);
+        sparse.retain(|_, value| *value % 2 == 0);
+        for key in new {
+            prove!(dense.remove(key).is_none())?;
+            prove!(sparse.remove(key).is_none())?;
+        }
+        prove!(dense
+            .iter()
+            .all(|(key, value)| armoire.get(key) == Some(value)))?;
+        prove!(sparse.iter().count() == dense.iter().count())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn secondary_maps_sync_removed_keys() -> Result {
+    <Vec<(u8, bool)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = Armoire::new();
+        let mut dense = SecondaryMap::new();
+        let mut sparse = SparseSecondaryMap::new();
+        let mut removed = Vec::new();
+        for &(value, remove) in pairs {
+            let key = armoire.insert(value);
+            dense.insert(key, value).unwrap();
+            sparse.insert(key, value).unwrap();
+            if remove {
+                removed.push(key);
+            }
+        }
+        armoire.retain(|key, _| !removed.contains(&key));
+        // Values of removed keys linger until the maps are synchronized.
+        prove!(removed.iter().all(|&key| dense.has(key) && sparse.has(key)))?;
+        dense.sync(&armoire);
+        sparse.sync(&armoire);
+        prove!(removed
+            .iter()
+            .all(|&key| !dense.has(key) && !sparse.has(key)))?;
+        prove!(dense.len() == armoire.len())?;
+        prove!(sparse.len() == armoire.len())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn join_yields_keys_present_in_every_source() -> Result {
+    <Vec<(u8, bool, bool)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = Armoire::new();
+        let mut dense = SecondaryMap::new();
+        let mut sparse = SparseSecondaryMap::new();
+        for &(value, left, right) in pairs {
+            let key = armoire.insert(value);
+            if left {
+                dense.insert(key, value as u32).unwrap();
+            }
+            if right {
+                sparse.insert(key, value as u64).unwrap();
+            }
+        }
+        let mut expected = armoire
+            .iter()
+            .filter(|&(key, _)| dense.has(key))
+            .map(|(key, &value)| (key, value, sparse.get(key).copied()))
+            .collect::<Vec<_>>();
+        expected.sort();
+
+        for (_, value, left, right) in join!(&armoire, &mut dense, maybe(&mut sparse)) {
+            *left += *value as u32;
+            if let Some(right) = right {
+                *right += 1;
+            }
+        }
+        let mut joined = join!(&dense, &armoire, maybe(&sparse))
+            .into_par_iter()
+            .map(|(key, &left, &value, right)| {
+                assert_eq!(left, value as u32 * 2);
+                (key, value, right.map(|right| right - 1))
+            })
+            .collect::<Vec<_>>();
+        joined.sort();
+        prove!(joined == expected)
+    })?;
+    Ok(())
+}
+
+#[test]
+fn table_keeps_columns_in_sync() -> Result {
+    <Vec<(u16, bool)>>::generator().check(COUNT, |pairs| {
+        let mut table = Table::new();
+        let mut armoire = Armoire::new();
+        let mut keys = Vec::new();
+        for &(value, remove) in pairs {
+            keys.push((
+                table.insert((value, value as u32 * 2)),
+                armoire.insert(value),
+                remove,
+            ));
+        }
+        {
+            let (_, (_, doubles), defer) = table.defer();
+            doubles.par_iter_mut().for_each(|double| *double += 1);
+            for &(key, _, remove) in &keys {
+                if remove {
+                    defer.remove([key]);
+                    defer.insert((u16::MAX, 0));
+                }
+            }
+        }
+        table.resolve();
+        for &(key, other, remove) in &keys {
+            if remove {
+                prove!(table.remove(key) == Err(Error::Stale))?;
+                armoire.remove(other).unwrap();
+                armoire.insert(u16::MAX);
+            }
+        }
+        prove!(table.len() == armoire.len())?;
+        let (_, (values, doubles)) = table.fork();
+        for (value, double) in values.iter().zip(doubles.iter_mut()) {
+            prove!(*double == 0 || *double == *value as u32 * 2 + 1)?;
+            *double = double.saturating_sub(1);
+        }
+        for (key, (&value, &double)) in table.iter() {
+            prove!(table.get(key) == Some((&value, &double)))?;
+        }
+        table
+            .par_iter_mut()
+            .for_each(|(_, (value, double))| *double = *value as u32);
+        prove!(table
+            .par_iter()
+            .all(|(key, (&value, &double))| double == value as u32
+                && table.get(key) == Some((&value, &double))))?;
+        let mut values = table
+            .par_iter()
+            .map(|(_, (&value, _))| value)
+            .collect::<Vec<_>>();
+        let mut expected = armoire.iter().map(|(_, &value)| value).collect::<Vec<_>>();
+        values.sort();
+        expected.sort();
+        prove!(values == expected)
+    })?;
+    Ok(())
+}
+
+#[derive(Fork, Clone, Debug, PartialEq)]
+#[fork(Movement(mut position, velocity), Sight(position, velocity), Aim(mut target))]
+struct Body {
+    position: i32,
+    velocity: i32,
+    target: Option<Key>,
+}
+
+#[test]
+fn fork_by_derived_views() -> Result {
+    <Vec<(i16, i16)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = pairs
+            .iter()
+            .map(|&(position, velocity)| Body {
+                position: position as i32,
+                velocity: velocity as i32,
+                target: None,
+            })
+            .collect::<Armoire<_>>();
+        let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        {
+            let (mut movements, _) = armoire.fork_by::<Movement, Aim>();
+            movements.par_iter_mut().for_each(|movement| {
+                *movement.position += movement.velocity;
+            });
+        }
+        {
+            let (mut aims, sights) = armoire.fork_by::<Aim, Sight>();
+            for (&key, aim) in keys.iter().zip(aims.iter_mut()) {
+                let (position, _) = sights.get(key).unwrap();
+                if *position > 0 {
+                    *aim.target = Some(key);
+                }
+            }
+            for &key in &keys {
+                let (position, velocity) = sights.get(key).unwrap();
+                let aim = aims.get_mut(key).unwrap();
+                prove!((*position > 0) == (*aim.target == Some(key)))?;
+                prove!(sights.get(key).unwrap() == (position, velocity))?;
+            }
+        }
+        prove!(keys.iter().zip(pairs).all(|(&key, &(position, velocity))| {
+            armoire.get(key).unwrap().position == position as i32 + velocity as i32
+        }))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn fork3_iterates_views_independently() -> Result {
+    <Vec<(i16, i16, u8)>>::generator().check(COUNT, |triples| {
+        let mut armoire = triples.iter().copied().collect::<Armoire<_>>();
+        {
+            let (positions, mut velocities, mut healths) = armoire.fork3(
+                |_, triple| unsafe { &(*triple).0 },
+                |_, triple| unsafe { &mut (*triple).1 },
+                |_, triple| unsafe { &mut (*triple).2 },
+            );
+            velocities
+                .par_iter_mut()
+                .zip(positions.par_iter())
+                .for_each(|(velocity, position)| *velocity = velocity.wrapping_add(*position));
+            for (health, position) in healths.iter_mut().zip(positions.iter()) {
+                *health = health.wrapping_add(*position as u8);
+            }
+        }
+        let expected = triples.iter().map(|&(position, velocity, health)| {
+            (
+                position,
+                velocity.wrapping_add(position),
+                health.wrapping_add(position as u8),
+            )
+        });
+        prove!(armoire.iter().map(|(_, &triple)| triple).eq(expected))
+    })?;
+    Ok(())
+}
+
+#[test]
+fn fork_filter_yields_matching_values() -> Result {
+    <Vec<(i16, bool)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = pairs.iter().copied().collect::<Armoire<_>>();
+        let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        {
+            let (mut values, flags) = armoire.fork_filter(
+                |_, pair| unsafe { (*pair).1.then(|| &mut (*pair).0) },
+                |key, pair| unsafe { Some((key, &(*pair).1)) },
+            );
+            let matching = pairs.iter().filter(|(_, flag)| *flag).count();
+            prove!(values.iter_mut().len() == matching)?;
+            prove!(flags.iter().len() == pairs.len())?;
+            values
+                .par_iter_mut()
+                .for_each(|value| *value = value.wrapping_add(1));
+            for &key in &keys {
+                let (_, &flag) = flags.get(key).unwrap();
+                prove!(values.get_mut(key).is_some() == flag)?;
+            }
+        }
+        let expected = pairs
+            .iter()
+            .map(|&(value, flag)| (value.wrapping_add(flag as i16), flag));
+        prove!(armoire.iter().map(|(_, &pair)| pair).eq(expected))
+    })?;
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "the item of a filtered element must remain `Some`")]
+fn filtered_items_must_remain_some() {
+    let mut armoire = Armoire::new();
+    armoire.insert(1);
+    let (mut values, _) = armoire.fork_filter(
+        |_, value| unsafe { (*value > 0).then(|| &mut *value) },
+        |key, _| Some(key),
+    );
+    values.iter_mut().for_each(|value| *value = -*value);
+    values.iter_mut().for_each(drop);
+}
+
+struct Move;
+struct Retarget;
+
+impl System<Body, i32> for Move {
+    type Write<'a> = Movement<'a>;
+    type Read<'a> = ();
+
+    fn run<'a>(
+        &self,
+        mut movements: Access<'a, Body, Movement<'a>>,
+        _: Access<'a, Body, ()>,
+        scale: &i32,
+        _: &Defer<'a, Body>,
+    ) {
+        movements
+            .par_iter_mut()
+            .for_each(|(_, movement)| *movement.position += movement.velocity * scale);
+    }
+}
+
+impl System<Body, i32> for Retarget {
+    type Write<'a> = Aim<'a>;
+    type Read<'a> = Sight<'a>;
+
+    fn run<'a>(
+        &self,
+        mut aims: Access<'a, Body, Aim<'a>>,
+        sights: Access<'a, Body, Sight<'a>>,
+        _: &i32,
+        defer: &Defer<'a, Body>,
+    ) {
+        aims.par_iter_mut().for_each(|(key, aim)| {
+            *aim.target = sights
+                .iter()
+                .filter(|&(other, _)| other != key)
+                .min_by_key(|&(_, (position, _))| *position)
+                .map(|(other, _)| other);
+            let (_, (position, _)) = sights.get(key).unwrap();
+            if *position < 0 {
+                defer.remove([key]);
+            }
+        });
+    }
+}
+
+#[test]
+fn schedule_runs_systems_in_order_and_resolves() -> Result {
+    <Vec<(i16, i16)>>::generator().check(COUNT, |pairs| {
+        let mut armoire = pairs
+            .iter()
+            .map(|&(position, velocity)| Body {
+                position: position as i32,
+                velocity: velocity as i32,
+                target: None,
+            })
+            .collect::<Armoire<_>>();
+        let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        let mut schedule = Schedule::new();
+        schedule.add(Move);
+        schedule.add(Retarget);
+        schedule.run(&mut armoire, &2);
+
+        let positions = pairs
+            .iter()
+            .map(|&(position, velocity)| position as i32 + velocity as i32 * 2)
+            .collect::<Vec<_>>();
+        prove!(keys.iter().enumerate().all(|(index, &key)| {
+            let position = positions[index];
+            let target = positions
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != index)
+                .min_by_key(|&(_, position)| position)
+                .map(|(other, _)| keys[other]);
+            match armoire.get(key) {
+                Some(body) => position >= 0 && body.position == position && body.target == target,
+                None => position < 0,
+            }
+        }))
+    })?;
+    Ok(())
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "the views of a fork overlap")]
+fn overlapping_fork_views_panic_in_debug() {
+    let mut armoire = Armoire::new();
+    armoire.insert_n([(1, 2), (3, 4)]);
+    let (mut left, right) = armoire.fork(
+        |_, pair| unsafe { &mut (*pair).0 },
+        |_, pair| unsafe { &*pair },
+    );
+    left.iter_mut()
+        .zip(right.iter())
+        .for_each(|(left, right)| *left += right.1);
+}
@@ -0,0 +1,212 @@
+//! Small, deterministic tests of the aliasing patterns of forks, meant to be run under Miri with
+//! `MIRIFLAGS="-Zmiri-tree-borrows -Zmiri-ignore-leaks" cargo +nightly miri test --test miri`. Tree borrows are
+//! required since the work-stealing of rayon goes through `crossbeam-epoch`, which violates stacked borrows, and the
+//! threads of the global rayon pool outlive the tests.
+
+use armoire::*;
+use rayon::prelude::*;
+
+#[derive(Clone)]
+struct Player {
+    position: [i32; 2],
+    target: Option<Key>,
+}
+
+#[derive(Clone)]
+struct Enemy {
+    position: [i32; 2],
+}
+
+#[derive(Clone)]
+#[repr(u8)]
+enum Entity {
+    Player(Player),
+    Enemy(Enemy),
+}
+
+#[derive(Fork)]
+#[fork(Movement(mut position), Sight(velocity), Aging(mut age))]
+struct Body {
+    position: i32,
+    velocity: i32,
+    age: u32,
+}
+
+struct Move;
+struct Age;
+
+impl System<Body, i32> for Move {
+    type Write<'a> = Movement<'a>;
+    type Read<'a> = Sight<'a>;
+
+    fn run<'a>(
+        &self,
+        mut movements: Access<'a, Body, Movement<'a>>,
+        sights: Access<'a, Body, Sight<'a>>,
+        scale: &i32,
+        _: &Defer<'a, Body>,
+    ) {
+        movements.par_iter_mut().for_each(|(key, movement)| {
+            let (_, (velocity,)) = sights.get(key).unwrap();
+            *movement.position += velocity * scale;
+        });
+    }
+}
+
+impl System<Body, i32> for Age {
+    type Write<'a> = Aging<'a>;
+    type Read<'a> = ();
+
+    fn run<'a>(
+        &self,
+        mut agings: Access<'a, Body, Aging<'a>>,
+        _: Access<'a, Body, ()>,
+        _: &i32,
+        defer: &Defer<'a, Body>,
+    ) {
+        agings.par_iter_mut().for_each(|(key, aging)| {
+            *aging.age += 1;
+            if *aging.age > 1 {
+                defer.remove([key]);
+            }
+        });
+    }
+}
+
+fn distance(left: [i32; 2], right: [i32; 2]) -> u32 {
+    left[0].abs_diff(right[0]) + left[1].abs_diff(right[1])
+}
+
+fn entities() -> Armoire<Entity> {
+    let mut entities = Armoire::new();
+    for index in 0..4 {
+        entities.insert(Entity::Player(Player {
+            position: [index, index],
+            target: None,
+        }));
+        entities.insert(Entity::Enemy(Enemy {
+            position: [index * 3, -index],
+        }));
+    }
+    entities
+}
+
+#[test]
+fn players_retarget_while_targets_are_read_in_parallel() {
+    let mut entities = entities();
+    for _ in 0..2 {
+        entities.scope(|mut entities, defer| {
+            let (mut players, targets) = entities.fork_filter(
+                |key, entity| unsafe {
+                    match *entity {
+                        Entity::Player(Player {
+                            ref mut target,
+                            ref position,
+                        }) => Some((key, target, position)),
+                        Entity::Enemy(_) => None,
+                    }
+                },
+                |key, entity| unsafe {
+                    match *entity {
+                        Entity::Player(Player { ref position, .. })
+                        | Entity::Enemy(Enemy { ref position }) => Some((key, position)),
+                    }
+                },
+            );
+            assert_eq!(players.iter_mut().count(), 4);
+            players.par_iter_mut().for_each(|(key, target, position)| {
+                defer.insert(Entity::Enemy(Enemy { position: [0; 2] }));
+                let chasing = target
+                    .and_then(|target| targets.get(target))
+                    .is_some_and(|(_, near)| distance(*position, *near) < 4);
+                if !chasing {
+                    *target = targets
+                        .par_iter()
+                        .filter(|pair| pair.0 != key)
+                        .min_by_key(|pair| distance(*position, *pair.1))
+                        .map(|pair| pair.0);
+                }
+            });
+        });
+    }
+
+    assert_eq!(entities.len(), 16);
+    for (_, entity) in entities.iter() {
+        if let Entity::Player(player) = entity {
+            assert!(player.target.is_some_and(|target| entities.has(target)));
+        }
+    }
+}
+
+#[test]
+fn shared_iteration_interleaves_with_exclusive_iteration() {
+    let mut armoire = (0..8)
+        .map(|index| (index, index * 2))
+        .collect::<Armoire<_>>();
+    let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+    {
+        let (mut lefts, rights) = armoire.fork(
+            |_, pair| unsafe { &mut (*pair).0 },
+            |_, pair| unsafe { &(*pair).1 },
+        );
+        for (left, right) in lefts.iter_mut().zip(rights.iter()) {
+            *left += right;
+            assert_eq!(rights.iter().last(), Some(&14));
+        }
+        for (&key, index) in keys.iter().zip(0..) {
+            *lefts.get_mut(key).unwrap() += rights.get(key).unwrap();
+            assert_eq!(rights.get(key), Some(&(index * 2)));
+        }
+    }
+    assert!(armoire
+        .iter()
+        .all(|(_, &(left, right))| left == right / 2 * 5));
+}
+
+#[test]
+fn derived_views_interleave() {
+    let mut armoire = (0..8)
+        .map(|index| Body {
+            position: index,
+            velocity: 1,
+            age: 0,
+        })
+        .collect::<Armoire<_>>();
+    let keys = armoire.iter().map(|(key, _)| key).collect::<Vec<_>>();
+    {
+        let (mut movements, sights) = armoire.fork_by::<Movement, Sight>();
+        movements.par_iter_mut().for_each(|movement| {
+            *movement.position += 1;
+            assert_eq!(sights.par_iter().count(), 8);
+        });
+        for (&key, movement) in keys.iter().zip(movements.iter_mut()) {
+            let (velocity,) = sights.get(key).unwrap();
+            *movement.position += *velocity;
+        }
+    }
+    assert!(keys
+        .iter()
+        .zip(0..)
+        .all(|(&key, index)| armoire.get(key).unwrap().position == index + 2));
+}
+
+#[test]
+fn concurrent_systems_access_disjoint_fields() {
+    let mut armoire = (0..8)
+        .map(|index| Body {
+            position: index,
+            velocity: index,
+            age: 0,
+        })
+        .collect::<Armoire<_>>();
+    let mut schedule = Schedule::new();
+    schedule.add(Move);
+    schedule.add(Age);
+    schedule.run(&mut armoire, &2);
+    assert!(armoire
+        .iter()
+        .zip(0..)
+        .all(|((_, body), index)| body.position == index * 3 && body.age == 1));
+    schedule.run(&mut armoire, &2);
+    assert!(armoire.is_empty());
+}